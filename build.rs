@@ -1,3 +1,4 @@
 fn main() -> shadow_rs::SdResult<()> {
-    shadow_rs::new()
+    shadow_rs::ShadowBuilder::builder().build()?;
+    Ok(())
 }
@@ -1,18 +1,48 @@
-use log::info;
+use clap::Parser;
+use waifu_calendar::http::{
+    config::{Config, ConfigArgs},
+    telemetry,
+};
 
 use std::error::Error;
 
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Serve birthday calendars of AniList favorites over HTTP"
+)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Check the configuration, then exit without starting the server
+    #[arg(long)]
+    check_config: bool,
+
+    /// Print the effective configuration as TOML, then exit without starting the server
+    #[arg(long)]
+    dump_config: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+    let cli = Cli::parse();
+    let config = Config::load(&cli.config)?;
+
+    if cli.dump_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
 
-    let bind_addr = "0.0.0.0:8080";
+    if cli.check_config {
+        config.validate()?;
+        println!("Configuration is valid");
+        return Ok(());
+    }
 
-    info!("starting Waifu Calendar on {}", bind_addr);
+    let _telemetry = telemetry::init(&config.log)?;
 
-    let app = waifu_calendar::http::router()?;
-    let listener = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
-    axum::serve(listener, app).await?;
+    waifu_calendar::http::serve(&config).await?;
 
     Ok(())
 }
@@ -1,10 +1,24 @@
-use waifu_calendar::{ics::BirthdayICalendar, Character, Characters};
+use waifu_calendar::{
+    ics::{
+        parse_date, parse_ics, BirthdayICalendar, CalendarFormat, CalendarOptions, EventStyle,
+        Horizon,
+    },
+    export::{BirthdayExport, ExportFormat},
+    vcard::{BirthdayVCard, VCardVersion},
+    Character, Characters,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::{Parser, Subcommand};
 use shadow_rs::shadow;
-use std::{env::current_dir, error::Error, fs::File, io::Write, path::PathBuf};
-use time::OffsetDateTime;
+use std::{
+    env::current_dir,
+    error::Error,
+    fs::{read_to_string, File},
+    io::Write,
+    path::PathBuf,
+};
+use time::{Date, OffsetDateTime, UtcOffset};
 
 shadow!(build);
 
@@ -20,16 +34,91 @@ enum Commands {
     /// Output birthdays to stdout
     Get {
         /// The AniList user to fetch favorite characters from
-        username: String,
+        #[arg(required_unless_present = "input")]
+        username: Option<String>,
+
+        /// Read characters from a saved ICalendar file instead of AniList
+        #[arg(short, long, value_name = "FILE")]
+        input: Option<PathBuf>,
     },
     /// Output birthdays to ICalendar (*.ics) format
     Ics {
         /// The AniList user to fetch favorite characters from
-        username: String,
+        #[arg(required_unless_present = "input")]
+        username: Option<String>,
+
+        /// Read characters from a saved ICalendar file instead of AniList
+        #[arg(short, long, value_name = "FILE")]
+        input: Option<PathBuf>,
 
         /// Output ICalendar to a file instead of to stdout
         #[arg(short, long, value_name = "FILE")]
         output: Option<PathBuf>,
+
+        /// IANA time zone to place the calendar in, like America/Denver
+        #[arg(long, value_name = "ZONE")]
+        timezone: Option<String>,
+
+        /// Event style: all-day, timed (00:00 to 23:59), or a start time like 09:00
+        #[arg(long, value_name = "STYLE", default_value = "all-day")]
+        style: EventStyle,
+
+        /// Calendar format: ics, jcal, or xcal
+        #[arg(long, value_name = "FORMAT", default_value = "ics")]
+        format: CalendarFormat,
+
+        /// Emit every birthday within this many years, instead of only the next one
+        #[arg(long, value_name = "YEARS", conflicts_with_all = ["from", "to"])]
+        years: Option<u32>,
+
+        /// Emit every birthday on or after this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        from: Option<Date>,
+
+        /// Emit every birthday on or before this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        to: Option<Date>,
+    },
+    /// Export birthdays as CSV, JSON, Markdown, org-mode, or terminal calendar files
+    Export {
+        /// The AniList user to fetch favorite characters from
+        #[arg(required_unless_present = "input")]
+        username: Option<String>,
+
+        /// Read characters from a saved ICalendar file instead of AniList
+        #[arg(short, long, value_name = "FILE")]
+        input: Option<PathBuf>,
+
+        /// Output to a file instead of to stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Export format: csv, json, markdown, org, org-timestamp, remind, calcurse, when,
+        /// or calendar. For khal, use the ics command and `khal import`
+        #[arg(short, long, value_name = "FORMAT")]
+        format: ExportFormat,
+
+        /// IANA time zone to count down to birthdays in, like America/Denver
+        #[arg(long, value_name = "ZONE")]
+        timezone: Option<String>,
+    },
+    /// Output characters as vCard (*.vcf) contacts with birthdays
+    Vcf {
+        /// The AniList user to fetch favorite characters from
+        #[arg(required_unless_present = "input")]
+        username: Option<String>,
+
+        /// Read characters from a saved ICalendar file instead of AniList
+        #[arg(short, long, value_name = "FILE")]
+        input: Option<PathBuf>,
+
+        /// Output vCards to a file instead of to stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// vCard version: 3.0 or 4.0
+        #[arg(long = "vcard-version", value_name = "VERSION", default_value = "4.0")]
+        vcard_version: VCardVersion,
     },
 }
 
@@ -38,44 +127,78 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Get { username }) => {
+        Some(Commands::Get { username, input }) => {
             let now = OffsetDateTime::now_utc();
-            print_birthday_table(username, &now).await?;
+
+            if let (Some(username), None) = (username, input) {
+                println!(
+                    "Fetching favorite character birthdays for username {}",
+                    username
+                );
+            }
+
+            let characters = load_characters(username.as_deref(), input.as_ref()).await?;
+            print_birthday_table(characters, &now);
         }
-        Some(Commands::Ics { username, output }) => {
+        Some(Commands::Ics {
+            username,
+            input,
+            output,
+            timezone,
+            style,
+            format,
+            years,
+            from,
+            to,
+        }) => {
             let cal = {
-                let now = OffsetDateTime::now_utc();
-                let mut characters = waifu_calendar::get_waifu_birthdays(username)
-                    .await
-                    .with_context(|| {
-                        format!("Failed to get waifu birthdays for user {}", username)
-                    })?;
+                let now = now_in_timezone(timezone.as_deref())?;
+                let horizon = Horizon::from_params(&now.date(), *years, *from, *to)?;
+                let mut characters = load_characters(username.as_deref(), input.as_ref()).await?;
                 characters.sort_by_upcoming(&now);
                 characters
-                    .to_ics(&now)
-                    .with_context(|| "Failed to convert character collection into ics")?
+                    .to_format_with_options(
+                        *format,
+                        &now,
+                        &CalendarOptions {
+                            timezone: timezone.clone(),
+                            event_style: *style,
+                            horizon,
+                            ..username
+                                .as_deref()
+                                .map(CalendarOptions::for_user)
+                                .unwrap_or_default()
+                        },
+                    )
+                    .with_context(|| "Failed to convert character collection into a calendar")?
             };
 
-            if let Some(path) = output {
-                let path = if path.is_absolute() {
-                    path.to_owned()
-                } else {
-                    let cwd = current_dir().with_context(|| "Failed to get current working dir")?;
-                    cwd.join(path)
-                };
-
-                let mut file = File::options()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .open(&path)
-                    .with_context(|| format!("Failed to open output ICS file at {:?}", &path))?;
-
-                file.write_all(&cal.as_bytes())
-                    .with_context(|| "Failed to write ICS to given output file")?;
-            } else {
-                println!("{}", cal);
-            }
+            write_output(output.as_ref(), &cal)?;
+        }
+        Some(Commands::Export {
+            username,
+            input,
+            output,
+            format,
+            timezone,
+        }) => {
+            let now = now_in_timezone(timezone.as_deref())?;
+            let mut characters = load_characters(username.as_deref(), input.as_ref()).await?;
+            characters.sort_by_upcoming(&now);
+
+            let exported = characters
+                .export(*format, &now)
+                .with_context(|| "Failed to export character collection")?;
+            write_output(output.as_ref(), &exported)?;
+        }
+        Some(Commands::Vcf {
+            username,
+            input,
+            output,
+            vcard_version,
+        }) => {
+            let characters = load_characters(username.as_deref(), input.as_ref()).await?;
+            write_output(output.as_ref(), &characters.to_vcf(*vcard_version))?;
         }
         &None => {}
     }
@@ -83,19 +206,67 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn print_birthday_table(username: &str, now: &OffsetDateTime) -> Result<()> {
-    println!(
-        "Fetching favorite character birthdays for username {}",
-        username
-    );
+/// Write to a file if given, otherwise to stdout.
+fn write_output(output: Option<&PathBuf>, contents: &str) -> Result<()> {
+    if let Some(path) = output {
+        let path = if path.is_absolute() {
+            path.to_owned()
+        } else {
+            let cwd = current_dir().with_context(|| "Failed to get current working dir")?;
+            cwd.join(path)
+        };
+
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open output file at {:?}", &path))?;
+
+        file.write_all(contents.as_bytes())
+            .with_context(|| "Failed to write to given output file")?;
+    } else {
+        println!("{}", contents);
+    }
+
+    Ok(())
+}
+
+fn now_in_timezone(timezone: Option<&str>) -> Result<OffsetDateTime> {
+    let now = OffsetDateTime::now_utc();
+
+    if let Some(name) = timezone {
+        let zone = tzdb::tz_by_name(name).ok_or_else(|| anyhow!("Unknown time zone {}", name))?;
+        let local_time_type = zone
+            .find_local_time_type(now.unix_timestamp())
+            .with_context(|| format!("Failed to find current offset for time zone {}", name))?;
+        let offset = UtcOffset::from_whole_seconds(local_time_type.ut_offset())?;
+
+        Ok(now.to_offset(offset))
+    } else {
+        Ok(now)
+    }
+}
+
+/// Read characters from a saved ICalendar file if given, otherwise fetch them from AniList.
+async fn load_characters(username: Option<&str>, input: Option<&PathBuf>) -> Result<Vec<Character>> {
+    if let Some(path) = input {
+        let contents = read_to_string(path)
+            .with_context(|| format!("Failed to read ICS file at {:?}", path))?;
 
-    let characters = {
-        let mut characters = waifu_calendar::get_waifu_birthdays(username)
+        parse_ics(&contents).with_context(|| format!("Failed to parse ICS file at {:?}", path))
+    } else {
+        let username = username.context("A username is required when not reading from a file")?;
+
+        waifu_calendar::get_waifu_birthdays(username)
             .await
-            .with_context(|| format!("Failed to get waifu birthdays for user {}", username))?;
-        characters.sort_by_upcoming(&now);
-        characters
-    };
+            .with_context(|| format!("Failed to get waifu birthdays for user {}", username))
+    }
+}
+
+fn print_birthday_table(mut characters: Vec<Character>, now: &OffsetDateTime) {
+    characters.sort_by_upcoming(now);
 
     let categories = characters.into_birthday_categories(now);
 
@@ -111,7 +282,7 @@ async fn print_birthday_table(username: &str, now: &OffsetDateTime) -> Result<()
         println!("\nUpcoming birthdays (next 30 days):\n");
 
         categories.within_thirty_days.iter().for_each(|character| {
-            println!("{}", character_row(character, &now));
+            println!("{}", character_row(character, now));
         });
     }
 
@@ -119,11 +290,9 @@ async fn print_birthday_table(username: &str, now: &OffsetDateTime) -> Result<()
         println!("\nFuture birthdays:\n");
 
         categories.future.iter().for_each(|character| {
-            println!("{}", character_row(character, &now));
+            println!("{}", character_row(character, now));
         });
     }
-
-    Ok(())
 }
 
 fn character_row(character: &Character, now: &OffsetDateTime) -> String {
@@ -0,0 +1,497 @@
+//! Tools for exporting birthdays to tables and other calendar tools.
+
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::{bail, Result};
+use serde::{Serialize, Serializer};
+use time::{Date, Month, OffsetDateTime};
+
+use crate::{duration_to_iso, Character};
+
+/// A way of writing out a list of birthdays.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row.
+    Csv,
+    /// A JSON array of characters with their next birthday.
+    Json,
+    /// A Markdown table.
+    Markdown,
+    /// An Emacs org-mode outline with `diary-anniversary` entries.
+    Org,
+    /// An Emacs org-mode outline with yearly repeating timestamps.
+    OrgTimestamp,
+    /// `REM` lines for `remind`.
+    Remind,
+    /// A `calcurse` appointments file with yearly events.
+    Calcurse,
+    /// A calendar file for `when`.
+    When,
+    /// A calendar file for BSD `calendar(1)`.
+    Calendar,
+}
+
+impl ExportFormat {
+    /// Get the MIME type of this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Markdown => "text/markdown",
+            ExportFormat::Org | ExportFormat::OrgTimestamp => "text/org",
+            ExportFormat::Remind
+            | ExportFormat::Calcurse
+            | ExportFormat::When
+            | ExportFormat::Calendar => "text/plain",
+        }
+    }
+
+    /// Get the usual file extension of this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Org | ExportFormat::OrgTimestamp => "org",
+            ExportFormat::Remind => "rem",
+            ExportFormat::Calcurse => "apts",
+            ExportFormat::When => "when",
+            ExportFormat::Calendar => "calendar",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    /// Parses `csv`, `json`, `markdown`, `org`, `org-timestamp`,
+    /// `remind`, `calcurse`, `when`, or `calendar`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "remind" => Ok(ExportFormat::Remind),
+            "calcurse" => Ok(ExportFormat::Calcurse),
+            "when" => Ok(ExportFormat::When),
+            "calendar" => Ok(ExportFormat::Calendar),
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "org" => Ok(ExportFormat::Org),
+            "org-timestamp" => Ok(ExportFormat::OrgTimestamp),
+            _ => bail!("Unknown export format {:?}", s),
+        }
+    }
+}
+
+/// A character along with when their birthday next occurs.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+pub struct ExportedCharacter<'a> {
+    #[serde(flatten)]
+    pub character: &'a Character,
+    /// The next date the character's birthday occurs on, written as `YYYY-MM-DD`.
+    #[serde(serialize_with = "serialize_date")]
+    pub next_occurrence: Date,
+    /// Whole days until the next birthday.
+    pub days_until: i64,
+    /// Time until the next birthday as an ISO 8601 duration.
+    pub countdown: String,
+}
+
+impl<'a> ExportedCharacter<'a> {
+    /// Describe a character's next birthday relative to `now`.
+    pub fn new(character: &'a Character, now: &OffsetDateTime) -> Result<Self> {
+        let next = character.birthday().next_occurrence(&now.date())?;
+        let til_next = character.birthday().til_next(now);
+
+        Ok(Self {
+            character,
+            next_occurrence: next,
+            days_until: (next - now.date()).whole_days(),
+            countdown: duration_to_iso(&til_next),
+        })
+    }
+}
+
+fn serialize_date<S: Serializer>(date: &Date, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(date)
+}
+
+/// Convert character birthdays into other formats.
+pub trait BirthdayExport {
+    /// Returns the characters written in the given format.
+    fn export(&self, format: ExportFormat, now: &OffsetDateTime) -> Result<String>;
+}
+
+impl BirthdayExport for Vec<Character> {
+    fn export(&self, format: ExportFormat, now: &OffsetDateTime) -> Result<String> {
+        let rows = self
+            .iter()
+            .map(|character| ExportedCharacter::new(character, now))
+            .collect::<Result<Vec<ExportedCharacter>>>()?;
+
+        match format {
+            ExportFormat::Csv => Ok(to_csv(&rows)),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(&rows)?),
+            ExportFormat::Markdown => Ok(to_markdown(&rows)),
+            ExportFormat::Org => Ok(to_org(&rows, |row| {
+                let birthday = row.character.birthday();
+                format!(
+                    "<%%(diary-anniversary {} {})>",
+                    birthday.month() as u8,
+                    birthday.day()
+                )
+            })),
+            ExportFormat::OrgTimestamp => Ok(to_org(&rows, org_timestamps)),
+            ExportFormat::Remind => Ok(to_remind(&rows)),
+            ExportFormat::Calcurse => Ok(to_calcurse(&rows)),
+            ExportFormat::When => Ok(to_when(&rows)),
+            ExportFormat::Calendar => Ok(to_calendar(&rows)),
+        }
+    }
+}
+
+fn to_csv(rows: &[ExportedCharacter]) -> String {
+    let mut csv = String::from("name,url,birthday,next_occurrence,days_until\r\n");
+
+    for row in rows {
+        let _ = write!(
+            csv,
+            "{},{},{},{},{}\r\n",
+            escape_csv(row.character.name()),
+            escape_csv(row.character.url()),
+            row.character.birthday().to_iso_string(),
+            row.next_occurrence,
+            row.days_until
+        );
+    }
+
+    csv
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn to_markdown(rows: &[ExportedCharacter]) -> String {
+    let mut markdown = String::from("| Name | Birthday | Next | Days until |\n");
+    markdown.push_str("| --- | --- | --- | ---: |\n");
+
+    for row in rows {
+        let name = escape_markdown(row.character.name());
+        let name = if row.character.url().is_empty() {
+            name
+        } else {
+            format!("[{}]({})", name, row.character.url())
+        };
+
+        let _ = writeln!(
+            markdown,
+            "| {} | {} | {} | {} |",
+            name,
+            row.character.birthday(),
+            row.next_occurrence,
+            row.days_until
+        );
+    }
+
+    markdown
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        if matches!(ch, '\\' | '|' | '[' | ']' | '*' | '_' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+
+    escaped
+}
+
+fn to_org(rows: &[ExportedCharacter], timestamp: impl Fn(&ExportedCharacter) -> String) -> String {
+    let mut org = String::from("* Birthdays\n");
+
+    for row in rows {
+        let name = row.character.name().replace(['[', ']'], "");
+
+        if row.character.url().is_empty() {
+            let _ = writeln!(org, "** {}", name);
+        } else {
+            let _ = writeln!(org, "** [[{}][{}]]", row.character.url(), name);
+        }
+
+        let _ = writeln!(org, "   {}", timestamp(row));
+    }
+
+    org
+}
+
+/// Timestamps repeating at the birthday's interval, or one for each occurrence
+/// when they stop repeating regularly, since org can't end a repeater.
+fn org_timestamps(row: &ExportedCharacter) -> String {
+    let next = row.next_occurrence;
+    let timestamp = |date: &Date| format!("{} {}", date, &date.weekday().to_string()[..3]);
+    let birthday = row.character.birthday();
+
+    match birthday.repetition(&next) {
+        Ok((interval, None)) => format!("<{} +{}y>", timestamp(&next), interval),
+        Ok((_, Some(until))) => birthday
+            .occurrences_between(&next, &until)
+            .iter()
+            .map(|date| format!("<{}>", timestamp(date)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Err(_) => format!("<{}>", timestamp(&next)),
+    }
+}
+
+fn birthday_message(character: &Character) -> String {
+    format!("{}'s birthday", character.name().replace(['\r', '\n'], " "))
+}
+
+/// Three-letter English month abbreviation, like `Mar`.
+fn month_abbreviation(month: Month) -> String {
+    month.to_string()[..3].to_string()
+}
+
+// Remind, when, and calendar(1) only trigger February 29th reminders in leap years,
+// matching `Birthday::occurrences_between`, so no year needs to be given.
+
+fn to_remind(rows: &[ExportedCharacter]) -> String {
+    let mut remind = String::new();
+
+    for row in rows {
+        let birthday = row.character.birthday();
+        let message = birthday_message(row.character)
+            .replace('%', "%%")
+            .replace('[', "[\"[\"]");
+
+        let _ = writeln!(
+            remind,
+            "REM {} {} MSG {}",
+            month_abbreviation(birthday.month()),
+            birthday.day(),
+            message
+        );
+    }
+
+    remind
+}
+
+fn to_calcurse(rows: &[ExportedCharacter]) -> String {
+    let mut apts = String::new();
+
+    for row in rows {
+        let next = row.next_occurrence;
+        let birthday = row.character.birthday();
+        // calcurse has no notion of leap days, so February 29th birthdays repeat every
+        // four years, and stop at the last leap year before a century that isn't one.
+        // `to_ics` repeats them yearly instead, which calendar apps skip in non-leap years.
+        let repeat = match birthday.repetition(&next) {
+            Ok((interval, None)) => format!("{}Y", interval),
+            Ok((interval, Some(until))) => format!(
+                "{}Y -> {:02}/{:02}/{:04}",
+                interval,
+                until.month() as u8,
+                until.day(),
+                until.year()
+            ),
+            Err(_) => "1Y".to_string(),
+        };
+
+        let _ = writeln!(
+            apts,
+            "{:02}/{:02}/{:04} [1] {{{}}} {}",
+            next.month() as u8,
+            next.day(),
+            next.year(),
+            repeat,
+            birthday_message(row.character)
+        );
+    }
+
+    apts
+}
+
+fn to_when(rows: &[ExportedCharacter]) -> String {
+    let mut when = String::new();
+
+    for row in rows {
+        let birthday = row.character.birthday();
+
+        let _ = writeln!(
+            when,
+            "* {} {} , {}",
+            month_abbreviation(birthday.month()).to_lowercase(),
+            birthday.day(),
+            birthday_message(row.character)
+        );
+    }
+
+    when
+}
+
+fn to_calendar(rows: &[ExportedCharacter]) -> String {
+    let mut calendar = String::new();
+
+    for row in rows {
+        let birthday = row.character.birthday();
+
+        let _ = writeln!(
+            calendar,
+            "{:02}/{:02}\t{}",
+            birthday.month() as u8,
+            birthday.day(),
+            birthday_message(row.character)
+        );
+    }
+
+    calendar
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Month, OffsetDateTime};
+
+    use super::{BirthdayExport, ExportFormat};
+    use crate::{Birthday, Character};
+
+    fn characters() -> Vec<Character> {
+        vec![
+            Character::new(
+                "Frieren",
+                "https://anilist.co/character/176754",
+                Birthday::new(Month::March, 8),
+            ),
+            Character::new("Stark, \"the Warrior\"", "", Birthday::new(Month::January, 2)),
+        ]
+    }
+
+    #[test]
+    fn export_csv() {
+        let csv = characters()
+            .export(ExportFormat::Csv, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert_eq!(
+            csv,
+            "name,url,birthday,next_occurrence,days_until\r\n\
+            Frieren,https://anilist.co/character/176754,03-08,1970-03-08,66\r\n\
+            \"Stark, \"\"the Warrior\"\"\",,01-02,1970-01-02,1\r\n"
+        );
+    }
+
+    #[test]
+    fn export_json() {
+        let json = characters()
+            .export(ExportFormat::Json, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value[0]["name"], "Frieren");
+        assert_eq!(value[0]["url"], "https://anilist.co/character/176754");
+        assert_eq!(value[0]["next_occurrence"], "1970-03-08");
+        assert_eq!(value[0]["days_until"], 66);
+        assert_eq!(value[0]["countdown"], "P66DT0H0M0S");
+    }
+
+    #[test]
+    fn export_markdown() {
+        let markdown = characters()
+            .export(ExportFormat::Markdown, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert_eq!(
+            markdown,
+            "| Name | Birthday | Next | Days until |\n\
+            | --- | --- | --- | ---: |\n\
+            | [Frieren](https://anilist.co/character/176754) | March 8 | 1970-03-08 | 66 |\n\
+            | Stark, \"the Warrior\" | January 2 | 1970-01-02 | 1 |\n"
+        );
+    }
+
+    #[test]
+    fn export_org() {
+        let org = characters()
+            .export(ExportFormat::Org, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert_eq!(
+            org,
+            "* Birthdays\n\
+            ** [[https://anilist.co/character/176754][Frieren]]\n   <%%(diary-anniversary 3 8)>\n\
+            ** Stark, \"the Warrior\"\n   <%%(diary-anniversary 1 2)>\n"
+        );
+    }
+
+    #[test]
+    fn export_org_timestamp() {
+        let org = characters()
+            .export(ExportFormat::OrgTimestamp, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert!(org.contains("   <1970-03-08 Sun +1y>\n"));
+
+        let leap = vec![Character::new("Leap", "", Birthday::new(Month::February, 29))]
+            .export(ExportFormat::OrgTimestamp, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert!(leap.contains("   <1972-02-29 Tue> <1976-02-29 Sun> "));
+        assert!(leap.ends_with(" <2096-02-29 Wed>\n"));
+        assert!(!leap.contains("+1y"));
+    }
+
+    #[test]
+    fn export_remind() {
+        let characters = vec![
+            Character::new("Frieren [100%]", "", Birthday::new(Month::March, 8)),
+            Character::new("Leap", "", Birthday::new(Month::February, 29)),
+        ];
+
+        let remind = characters
+            .export(ExportFormat::Remind, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert_eq!(
+            remind,
+            "REM Mar 8 MSG Frieren [\"[\"]100%%]'s birthday\n\
+            REM Feb 29 MSG Leap's birthday\n"
+        );
+    }
+
+    #[test]
+    fn export_calcurse() {
+        let characters = vec![
+            Character::new("Frieren", "", Birthday::new(Month::March, 8)),
+            Character::new("Leap", "", Birthday::new(Month::February, 29)),
+        ];
+
+        let apts = characters
+            .export(ExportFormat::Calcurse, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert_eq!(
+            apts,
+            "03/08/1970 [1] {1Y} Frieren's birthday\n\
+            02/29/1972 [1] {4Y -> 02/29/2096} Leap's birthday\n"
+        );
+    }
+
+    #[test]
+    fn export_when_and_calendar() {
+        let when = characters()
+            .export(ExportFormat::When, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+        let calendar = characters()
+            .export(ExportFormat::Calendar, &OffsetDateTime::UNIX_EPOCH)
+            .unwrap();
+
+        assert!(when.starts_with("* mar 8 , Frieren's birthday\n"));
+        assert!(calendar.starts_with("03/08\tFrieren's birthday\n"));
+    }
+}
@@ -0,0 +1,363 @@
+//! Tools for making Atom and RSS feeds of birthdays.
+
+use std::fmt::Write;
+
+use anyhow::Result;
+use time::{
+    format_description::well_known::{Rfc2822, Rfc3339},
+    Date, Duration, OffsetDateTime, Time, UtcOffset,
+};
+use tz::TimeZone;
+
+use crate::{escape_xml, Character};
+
+/// The authority and fixed date that entry IDs are minted under, as a `tag:` URI prefix.
+const TAG_PREFIX: &str = "tag:waifu-calendar,2024:";
+
+/// Describes a birthday feed and which birthdays go into it.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FeedOptions {
+    /// Title of the feed.
+    pub title: String,
+    /// A permanent, unique IRI identifying the feed.
+    pub id: String,
+    /// Web page the feed is about.
+    pub link: String,
+    /// How far back to publish birthdays, counting today.
+    pub days: u16,
+    /// Publish one entry per day listing every birthday,
+    /// instead of one entry per birthday.
+    pub digest: bool,
+    /// Time zone whose local midnight entries are published at,
+    /// instead of the offset of the current time.
+    pub time_zone: Option<TimeZone>,
+}
+
+impl FeedOptions {
+    /// Build options for an AniList user's feed of the last 30 days of birthdays.
+    pub fn for_user(username: &str) -> Self {
+        let link = format!("https://anilist.co/user/{}/", username);
+
+        Self {
+            title: format!("{}'s waifu birthdays", username),
+            id: link.clone(),
+            link,
+            days: 30,
+            digest: false,
+            time_zone: None,
+        }
+    }
+}
+
+/// A birthday or set of birthdays published at local midnight on a date.
+struct FeedEntry<'a> {
+    id: String,
+    title: String,
+    date: Date,
+    published: OffsetDateTime,
+    characters: Vec<&'a Character>,
+}
+
+/// Convert character birthdays into syndication feeds.
+pub trait BirthdayFeed {
+    /// Returns an Atom feed with an entry for each recent birthday.
+    fn to_atom(&self, now: &OffsetDateTime, options: &FeedOptions) -> Result<String>;
+
+    /// Returns an RSS 2.0 feed with an item for each recent birthday.
+    fn to_rss(&self, now: &OffsetDateTime, options: &FeedOptions) -> Result<String>;
+}
+
+impl BirthdayFeed for Vec<Character> {
+    fn to_atom(&self, now: &OffsetDateTime, options: &FeedOptions) -> Result<String> {
+        let entries = feed_entries(self, now, options);
+        let updated = entries
+            .first()
+            .map(|entry| entry.published)
+            .unwrap_or(*now)
+            .format(&Rfc3339)?;
+
+        let mut xml = String::new();
+        xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        write!(xml, "<title>{}</title>", escape_xml(&options.title))?;
+        write!(xml, "<id>{}</id>", escape_xml(&options.id))?;
+        write!(xml, r#"<link href="{}"/>"#, escape_xml(&options.link))?;
+        write!(xml, "<updated>{}</updated>", updated)?;
+        xml.push_str("<author><name>Waifu Calendar</name></author>");
+
+        for entry in entries {
+            let published = entry.published.format(&Rfc3339)?;
+
+            xml.push_str("<entry>");
+            write!(xml, "<title>{}</title>", escape_xml(&entry.title))?;
+            write!(xml, "<id>{}</id>", escape_xml(&entry.id))?;
+            write!(xml, "<published>{}</published>", published)?;
+            write!(xml, "<updated>{}</updated>", published)?;
+
+            for character in entry.characters.iter().filter(|c| !c.url().is_empty()) {
+                write!(xml, r#"<link href="{}"/>"#, escape_xml(character.url()))?;
+            }
+
+            write!(
+                xml,
+                r#"<content type="html">{}</content>"#,
+                escape_xml(&entry_html(&entry))
+            )?;
+            xml.push_str("</entry>");
+        }
+
+        xml.push_str("</feed>");
+
+        Ok(xml)
+    }
+
+    fn to_rss(&self, now: &OffsetDateTime, options: &FeedOptions) -> Result<String> {
+        let entries = feed_entries(self, now, options);
+
+        let mut xml = String::new();
+        xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<rss version="2.0"><channel>"#);
+        write!(xml, "<title>{}</title>", escape_xml(&options.title))?;
+        write!(xml, "<link>{}</link>", escape_xml(&options.link))?;
+        write!(
+            xml,
+            "<description>{}</description>",
+            escape_xml(&options.title)
+        )?;
+        write!(xml, "<lastBuildDate>{}</lastBuildDate>", now.format(&Rfc2822)?)?;
+
+        for entry in entries {
+            xml.push_str("<item>");
+            write!(xml, "<title>{}</title>", escape_xml(&entry.title))?;
+
+            if let Some(character) = entry.characters.iter().find(|c| !c.url().is_empty()) {
+                write!(xml, "<link>{}</link>", escape_xml(character.url()))?;
+            }
+
+            write!(
+                xml,
+                r#"<guid isPermaLink="false">{}</guid>"#,
+                escape_xml(&entry.id)
+            )?;
+            write!(xml, "<pubDate>{}</pubDate>", entry.published.format(&Rfc2822)?)?;
+            write!(
+                xml,
+                "<description>{}</description>",
+                escape_xml(&entry_html(&entry))
+            )?;
+            xml.push_str("</item>");
+        }
+
+        xml.push_str("</channel></rss>");
+
+        Ok(xml)
+    }
+}
+
+/// Collect the birthdays within the feed's window, newest first.
+fn feed_entries<'a>(
+    characters: &'a [Character],
+    now: &OffsetDateTime,
+    options: &FeedOptions,
+) -> Vec<FeedEntry<'a>> {
+    let today = now.date();
+    let from = today - Duration::days(i64::from(options.days.max(1)) - 1);
+
+    let mut birthdays: Vec<(Date, &Character)> = characters
+        .iter()
+        .flat_map(|character| {
+            character
+                .birthday()
+                .occurrences_between(&from, &today)
+                .into_iter()
+                .map(move |date| (date, character))
+        })
+        .collect();
+
+    birthdays.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let published = |date: Date| {
+        let offset = options
+            .time_zone
+            .as_ref()
+            .and_then(|zone| midnight_offset(zone, date))
+            .unwrap_or(now.offset());
+
+        OffsetDateTime::new_in_offset(date, Time::MIDNIGHT, offset)
+    };
+
+    if options.digest {
+        let mut entries: Vec<FeedEntry> = vec![];
+
+        for (date, character) in birthdays {
+            match entries.last_mut() {
+                Some(entry) if entry.date == date => entry.characters.push(character),
+                _ => entries.push(FeedEntry {
+                    id: format!("{}{}:{}#digest", TAG_PREFIX, date, tag_specific(&options.id)),
+                    title: format!("Birthdays on {}", date),
+                    date,
+                    published: published(date),
+                    characters: vec![character],
+                }),
+            }
+        }
+
+        entries
+    } else {
+        birthdays
+            .into_iter()
+            .map(|(date, character)| {
+                let specific = if character.url().is_empty() {
+                    character.name()
+                } else {
+                    character.url()
+                };
+
+                FeedEntry {
+                    id: format!("{}{}:{}", TAG_PREFIX, date, tag_specific(specific)),
+                    title: format!("{}'s Birthday", character.name()),
+                    date,
+                    published: published(date),
+                    characters: vec![character],
+                }
+            })
+            .collect()
+    }
+}
+
+/// Find the offset from UTC of local midnight on a date in a time zone.
+fn midnight_offset(zone: &TimeZone, date: Date) -> Option<UtcOffset> {
+    let midnight = OffsetDateTime::new_utc(date, Time::MIDNIGHT).unix_timestamp();
+
+    // Local midnight is offset from midnight UTC by the zone's offset around then.
+    let guess = zone.find_local_time_type(midnight).ok()?.ut_offset();
+    let offset = zone
+        .find_local_time_type(midnight - i64::from(guess))
+        .ok()?
+        .ut_offset();
+
+    UtcOffset::from_whole_seconds(offset).ok()
+}
+
+/// Percent-encode characters that can't appear in the specific part of a tag URI.
+fn tag_specific(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~:/?#@!$&'()*+,;=".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+
+    encoded
+}
+
+fn entry_html(entry: &FeedEntry) -> String {
+    let mut html = String::new();
+
+    for character in &entry.characters {
+        html.push_str("<p>");
+
+        if let Some(image) = character.image() {
+            let _ = write!(
+                html,
+                r#"<img src="{}" alt="{}"/><br/>"#,
+                escape_xml(image),
+                escape_xml(character.name())
+            );
+        }
+
+        if character.url().is_empty() {
+            let _ = write!(html, "Today is {}'s birthday!", escape_xml(character.name()));
+        } else {
+            let _ = write!(
+                html,
+                r#"Today is <a href="{}">{}</a>'s birthday!"#,
+                escape_xml(character.url()),
+                escape_xml(character.name())
+            );
+        }
+
+        html.push_str("</p>");
+    }
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Month};
+    use tz::TimeZone;
+
+    use super::{BirthdayFeed, FeedOptions};
+    use crate::{Birthday, Character};
+
+    fn characters() -> Vec<Character> {
+        vec![
+            Character::new(
+                "Frieren",
+                "https://anilist.co/character/176754",
+                Birthday::new(Month::March, 8),
+            )
+            .with_image("https://s4.anilist.co/character/frieren.png"),
+            Character::new("Fern", "", Birthday::new(Month::March, 8)),
+            Character::new("Himmel", "", Birthday::new(Month::March, 13)),
+            Character::new("Stark", "", Birthday::new(Month::January, 2)),
+        ]
+    }
+
+    #[test]
+    fn to_atom_publishes_recent_birthdays_at_local_midnight() {
+        let now = datetime!(2024-03-10 12:00 -7);
+        let atom = characters()
+            .to_atom(&now, &FeedOptions::for_user("Owldown"))
+            .unwrap();
+
+        assert!(atom.contains("<title>Owldown&apos;s waifu birthdays</title>"));
+        assert!(atom.contains(
+            "<entry><title>Frieren&apos;s Birthday</title>\
+            <id>tag:waifu-calendar,2024:2024-03-08:https://anilist.co/character/176754</id>\
+            <published>2024-03-08T00:00:00-07:00</published>"
+        ));
+        assert!(atom.contains(r#"<link href="https://anilist.co/character/176754"/>"#));
+        assert!(atom.contains("&lt;img src=&quot;https://s4.anilist.co/character/frieren.png&quot;"));
+        assert!(atom.contains("<id>tag:waifu-calendar,2024:2024-03-08:Fern</id>"));
+        assert!(!atom.contains("Himmel"));
+        assert!(!atom.contains("Stark"));
+    }
+
+    #[test]
+    fn to_rss_digest_groups_birthdays_by_day() {
+        let now = datetime!(2024-03-10 12:00 UTC);
+        let options = FeedOptions {
+            digest: true,
+            ..FeedOptions::for_user("Owldown")
+        };
+
+        let rss = characters().to_rss(&now, &options).unwrap();
+
+        assert_eq!(rss.matches("<item>").count(), 1);
+        assert!(rss.contains("<title>Birthdays on 2024-03-08</title>"));
+        assert!(rss.contains(
+            r#"<guid isPermaLink="false">tag:waifu-calendar,2024:2024-03-08:https://anilist.co/user/Owldown/#digest</guid>"#
+        ));
+        assert!(rss.contains("<pubDate>Fri, 08 Mar 2024 00:00:00 +0000</pubDate>"));
+    }
+
+    #[test]
+    fn entries_are_published_at_midnight_in_their_own_offset() {
+        // Daylight saving time began in Denver on March 10th, 2024.
+        let now = datetime!(2024-03-14 12:00 -6);
+        let options = FeedOptions {
+            time_zone: Some(TimeZone::from_posix_tz("MST7MDT,M3.2.0,M11.1.0").unwrap()),
+            ..FeedOptions::for_user("Owldown")
+        };
+
+        let atom = characters().to_atom(&now, &options).unwrap();
+
+        assert!(atom.contains("<published>2024-03-08T00:00:00-07:00</published>"));
+        assert!(atom.contains("<published>2024-03-13T00:00:00-06:00</published>"));
+    }
+}
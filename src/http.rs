@@ -1,23 +1,60 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
-
-use crate::{ics::BirthdayICalendar, BirthdayCategories, Character, Characters};
+mod api;
+mod assets;
+mod cache;
+mod conditional;
+pub mod config;
+mod error;
+mod health;
+mod listen;
+mod metrics;
+mod negotiate;
+mod refresh;
+pub mod telemetry;
+mod upstream;
+
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use crate::{
+    ics::{parse_date, BirthdayICalendar, CalendarFormat, CalendarOptions, EventStyle, Horizon},
+    export::{BirthdayExport, ExportFormat},
+    feed::{BirthdayFeed, FeedOptions},
+    vcard::{BirthdayVCard, VCardVersion},
+    duration_to_iso, BirthdayCategories, Character, Characters,
+};
 use axum::{
-    extract::{Query, State},
-    http::{header, StatusCode},
-    response::{Html, IntoResponse, Response},
+    extract::{Path, Query, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
     routing::get,
     Router,
 };
-use handlebars::{to_json, DirectorySourceOptions, Handlebars};
-use log::{error, info};
-use moka::future::Cache;
-use recloser::{AsyncRecloser, Recloser};
+use handlebars::{to_json, Handlebars};
+use recloser::AsyncRecloser;
+use reqwest::Url;
 use serde::Serialize;
 use time::{Duration, OffsetDateTime, UtcOffset};
-use tower_http::services::ServeFile;
+use tokio::{
+    sync::watch,
+    task::{AbortHandle, JoinSet},
+};
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+
+use self::{
+    assets::Assets,
+    cache::{Favorites, FavoritesCache, FetchError},
+    conditional::conditional_response,
+    config::Config,
+    error::AppError,
+    metrics::Metrics,
+    negotiate::{negotiate, Representation},
+    upstream::Upstream,
+};
 
-use anyhow::Result;
-use tz::TimeZone;
+use anyhow::{bail, Result};
+
+/// Color given to subscribed calendars unless the `color` parameter overrides it.
+const DEFAULT_CALENDAR_COLOR: &str = "palevioletred";
 
 #[derive(Serialize)]
 struct NoHandlebarsData;
@@ -25,65 +62,190 @@ struct NoHandlebarsData;
 struct AppState<'a> {
     handlebars: Handlebars<'a>,
     circuit_breaker: AsyncRecloser,
-    cache: Cache<String, Vec<Character>>,
+    cache: FavoritesCache,
+    anilist_endpoint: String,
+    public_url: Option<Url>,
+    metrics: Arc<Metrics>,
+    upstream: Arc<Upstream>,
 }
 
 impl<'a> AppState<'a> {
     pub fn new(
-        cache: Cache<String, Vec<Character>>,
+        cache: FavoritesCache,
         handlebars: Handlebars<'a>,
         circuit_breaker: AsyncRecloser,
+        breaker_open_wait: std::time::Duration,
+        anilist_endpoint: String,
+        public_url: Option<Url>,
     ) -> Self {
         Self {
+            metrics: Arc::new(Metrics::new(&cache)),
+            upstream: Arc::new(Upstream::new(breaker_open_wait)),
             cache,
             handlebars,
             circuit_breaker,
+            anilist_endpoint,
+            public_url,
         }
     }
 }
 
-pub fn router() -> Result<Router> {
-    let mut assets_path = PathBuf::new();
-    assets_path.push(std::env::var("WAIFU_ASSETS").unwrap_or(".".to_string()));
+/// Build the server's routes.
+pub async fn router(config: &Config) -> Result<Router> {
+    Ok(app(config).await?.router)
+}
 
-    info!("Loading assets from {:?}", assets_path);
+/// Serve the routes on every configured address until SIGTERM or SIGINT.
+///
+/// On shutdown, stop accepting connections, then wait up to the drain timeout
+/// for open requests, and up to the drain timeout again for background refreshes.
+pub async fn serve(config: &Config) -> Result<()> {
+    let App {
+        router,
+        state,
+        refresher,
+    } = app(config).await?;
+    let listeners = listen::bind(&config.server.bind).await?;
+
+    let (shutdown, shutting_down) = watch::channel(false);
+    let mut servers = JoinSet::new();
+
+    for listener in listeners {
+        info!("starting Waifu Calendar on {}", listener);
+
+        let mut shutting_down = shutting_down.clone();
+        let signal = async move {
+            let _ = shutting_down.wait_for(|shutting_down| *shutting_down).await;
+        };
+
+        servers.spawn(listener.serve(router.clone(), signal));
+    }
 
-    let mut handlebars = Handlebars::new();
-    handlebars.set_strict_mode(true);
-    handlebars.register_templates_directory(
-        assets_path.join("templates"),
-        DirectorySourceOptions::default(),
-    )?;
+    tokio::select! {
+        () = listen::shutdown_signal() => {}
+        Some(served) = servers.join_next() => {
+            served??;
+            bail!("Stopped listening unexpectedly");
+        }
+    }
 
-    let circuit_breaker = AsyncRecloser::from(Recloser::default());
+    let drain_timeout = config.server.drain_timeout();
+    info!(
+        "Shutting down, waiting up to {:?} for open requests",
+        drain_timeout
+    );
 
-    let cache = Cache::builder()
-        .weigher(|_key, value: &Vec<Character>| -> u32 {
-            value.len().try_into().unwrap_or(u32::MAX)
-        })
-        .max_capacity(1024 * 1024)
-        .time_to_live(std::time::Duration::from_secs(15 * 60))
-        .build();
+    // Stop refreshing first, so no new refreshes start while the cache is flushed.
+    // Favorites are saved atomically, so one cut short leaves the old ones in place.
+    if let Some(refresher) = refresher {
+        refresher.abort();
+    }
+
+    let _ = shutdown.send(true);
+
+    drain(servers, &state.cache, drain_timeout).await;
 
-    let router = Router::new()
+    for addr in &config.server.bind {
+        if let listen::ListenAddr::Unix(path) = addr {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait up to `timeout` for the servers to finish their open requests,
+/// then flush the cache whether they did or not, so refreshed favorites are still saved.
+async fn drain(
+    mut servers: JoinSet<std::io::Result<()>>,
+    cache: &FavoritesCache,
+    timeout: std::time::Duration,
+) {
+    let drained = tokio::time::timeout(timeout, async {
+        while let Some(served) = servers.join_next().await {
+            if let Ok(Err(err)) = served {
+                warn!("Error while shutting down: {:?}", err);
+            }
+        }
+    })
+    .await;
+
+    if drained.is_err() {
+        warn!("Gave up waiting for open requests after {:?}", timeout);
+    }
+
+    if tokio::time::timeout(timeout, cache.flush()).await.is_err() {
+        warn!("Gave up waiting for background refreshes after {:?}", timeout);
+    }
+}
+
+/// The server's routes, and what's running behind them.
+struct App {
+    router: Router,
+    state: Arc<AppState<'static>>,
+    /// Refreshes popular users' favorites, unless refreshing is disabled.
+    refresher: Option<AbortHandle>,
+}
+
+async fn app(config: &Config) -> Result<App> {
+    config.validate()?;
+
+    let assets = Assets::new(&config.server);
+
+    info!("Loading assets from {}", assets);
+
+    let handlebars = assets.templates(config.server.dev_mode)?;
+
+    let circuit_breaker = config.circuit_breaker.build();
+
+    let cache = FavoritesCache::open(&config.cache).await?;
+
+    let state = Arc::new(AppState::new(
+        cache,
+        handlebars,
+        circuit_breaker,
+        config.circuit_breaker.open_wait(),
+        config.anilist.endpoint.clone(),
+        config.public_url()?,
+    ));
+
+    let refresher = refresh::spawn(state.clone(), config.cache.refresh_budget);
+
+    let router = assets
+        .serve(Router::new())
         .route("/", get(get_index))
-        .route_service(
-            "/assets/pico.min.css",
-            ServeFile::new(assets_path.join("assets/pico.min.css")),
-        )
-        .route_service(
-            "/assets/frieren.jpg",
-            ServeFile::new(assets_path.join("assets/frieren.jpg")),
+        .route("/ics", get(redirect_birthday_ics))
+        .route("/cal", get(redirect_birthday_html))
+        .route("/u/{username}", get(get_user))
+        .route("/u/{username}/calendar.ics", get(get_birthday_ics))
+        .route("/vcf", get(get_birthday_vcf))
+        .route("/export", get(get_birthday_export))
+        .route("/feed.atom", get(get_birthday_atom))
+        .route("/feed.rss", get(get_birthday_rss))
+        .route("/api/openapi.json", get(api::get_openapi))
+        .route(
+            "/api/v1/users/{username}/birthdays",
+            get(api::get_user_birthdays),
         )
-        .route_service(
-            "/humans.txt",
-            ServeFile::new(assets_path.join("assets/humans.txt")),
-        )
-        .route("/ics", get(get_birthday_ics))
-        .route("/cal", get(get_birthday_html))
-        .with_state(Arc::new(AppState::new(cache, handlebars, circuit_breaker)));
-
-    Ok(router)
+        .route("/metrics", get(metrics::get_metrics))
+        .route("/healthz", get(health::get_healthz))
+        .route("/readyz", get(health::get_readyz))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            error::render_error_pages,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.metrics.clone(),
+            metrics::track_requests,
+        ))
+        .layer(middleware::from_fn(telemetry::trace_requests))
+        .with_state(state.clone());
+
+    Ok(App {
+        router,
+        state,
+        refresher,
+    })
 }
 
 #[derive(Serialize)]
@@ -91,12 +253,12 @@ struct IndexHtml<'a> {
    timezones: &'a[&'a str],
 }
 
-async fn get_index(State(state): State<Arc<AppState<'_>>>) -> Result<Response, Response> {
+async fn get_index(State(state): State<Arc<AppState<'_>>>) -> Result<Response, AppError> {
     let data = IndexHtml { timezones: tzdb::TZ_NAMES };
     let body = state
         .handlebars
         .render("index", &data)
-        .map_err(|_| render_internal_server_error(&state))?;
+        .map_err(|_| AppError::internal_error())?;
 
     Ok((Html::from(body)).into_response())
 }
@@ -115,7 +277,7 @@ struct CharacterHtml {
 impl CharacterHtml {
     pub fn new(character: &Character, now: &OffsetDateTime) -> Result<Self> {
         let next_occurrence = character.birthday().next_occurrence(&now.date())?;
-        let til_next = character.birthday().til_next(&now);
+        let til_next = character.birthday().til_next(now);
 
         Ok(Self {
             url: character.url.to_string(),
@@ -129,17 +291,12 @@ impl CharacterHtml {
     }
 }
 
-fn duration_to_iso(dur: &Duration) -> String {
-    let days = dur.whole_days();
-    let hours = (*dur - Duration::days(dur.whole_days())).whole_hours();
-    let minutes = (*dur - Duration::hours(dur.whole_hours())).whole_minutes();
-    let seconds = (*dur - Duration::minutes(dur.whole_minutes())).whole_seconds();
-    format!("P{}DT{}H{}M{}S", days, hours, minutes, seconds)
-}
-
 #[derive(Debug, Serialize)]
 struct BirthdayHtml {
     username: String,
+    calendar_url: String,
+    webcal_url: String,
+    stale: bool,
     today: Vec<CharacterHtml>,
     within_thirty_days: Vec<CharacterHtml>,
     future: Vec<CharacterHtml>,
@@ -148,198 +305,689 @@ struct BirthdayHtml {
 impl BirthdayHtml {
     pub fn new(
         username: &str,
+        calendar_url: &Url,
+        stale: bool,
         categories: BirthdayCategories,
         now: &OffsetDateTime,
     ) -> Result<BirthdayHtml> {
         Ok(Self {
             username: username.to_string(),
+            calendar_url: calendar_url.to_string(),
+            webcal_url: webcal_url(calendar_url),
+            stale,
             today: categories
                 .today
                 .iter()
-                .filter_map(|c| CharacterHtml::new(c, &now).ok())
+                .filter_map(|c| CharacterHtml::new(c, now).ok())
                 .collect(),
             within_thirty_days: categories
                 .within_thirty_days
                 .iter()
-                .filter_map(|c| CharacterHtml::new(c, &now).ok())
+                .filter_map(|c| CharacterHtml::new(c, now).ok())
                 .collect(),
             future: categories
                 .future
                 .iter()
-                .filter_map(|c| CharacterHtml::new(c, &now).ok())
+                .filter_map(|c| CharacterHtml::new(c, now).ok())
                 .collect(),
         })
     }
 }
 
-async fn get_birthday_html(
+/// Redirect the old `/cal?username=` form to the user's calendar page.
+async fn redirect_birthday_html(RawQuery(query): RawQuery) -> Result<Redirect, StatusCode> {
+    redirect_to_user_path(query, None)
+}
+
+/// Serve a user's birthdays in whichever representation the `Accept` header prefers,
+/// defaulting to the calendar page.
+async fn get_user(
     State(state): State<Arc<AppState<'_>>>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
     Query(query): Query<HashMap<String, String>>,
-) -> Result<Response, Response> {
-    let cal: BirthdayHtml = {
-        let username = query
-            .get("username")
-            .ok_or(StatusCode::UNPROCESSABLE_ENTITY.into_response())?;
+) -> Response {
+    let Some(representation) = negotiate(&headers, Representation::Html) else {
+        return (
+            StatusCode::NOT_ACCEPTABLE,
+            [(header::VARY, HeaderValue::from_static("accept"))],
+        )
+            .into_response();
+    };
 
-        if username.is_empty() {
-            return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
+    let result = match representation {
+        Representation::Html => render_birthday_html(&state, &headers, &username, &query).await,
+        Representation::Calendar(format) => {
+            render_birthday_calendar(&state, &headers, &username, &query, format).await
         }
+        Representation::Json => api::user_birthdays(&state, &headers, &username, &query).await,
+        Representation::VCard => render_birthday_vcf(&state, &username, &query).await,
+    };
 
-        let cache_result = state.cache.get(username).await;
-        let cache_hit = cache_result.is_some();
+    let mut response = result
+        .map_err(|e| e.represented_as(representation))
+        .into_response();
 
-        let mut characters = if let Some(characters) = cache_result {
-            Ok(characters)
-        } else {
-            state
-                .circuit_breaker
-                .call_with(should_melt, crate::get_waifu_birthdays(&username))
-                .await
-        }
-        .map_err(|e| match e {
-            recloser::Error::Inner(err) => match err.downcast::<crate::Error>() {
-                Ok(crate::Error::UserNotFound(_)) => {
-                    let body = state
-                        .handlebars
-                        .render("user_not_found", &NoHandlebarsData {})
-                        .unwrap();
-                    (StatusCode::NOT_FOUND, Html::from(body)).into_response()
-                }
-                Ok(crate::Error::RateLimited) => {
-                    let body = state
-                        .handlebars
-                        .render("too_many_requests", &NoHandlebarsData {})
-                        .unwrap();
-                    (StatusCode::TOO_MANY_REQUESTS, Html::from(body)).into_response()
-                }
-                Err(err) => {
-                    error!("Error contacting AniList: {:?}", err);
-                    let body = state
-                        .handlebars
-                        .render("internal_server_error", &NoHandlebarsData {})
-                        .unwrap();
-                    (StatusCode::INTERNAL_SERVER_ERROR, Html::from(body)).into_response()
-                }
-                Ok(other_err) => {
-                    error!("Unknown error fetching from AniList: {:?}", other_err);
-                    let body = state
-                        .handlebars
-                        .render("internal_server_error", &NoHandlebarsData {})
-                        .unwrap();
-                    (StatusCode::INTERNAL_SERVER_ERROR, Html::from(body)).into_response()
-                }
-            },
-            recloser::Error::Rejected => {
-                let body = state
-                    .handlebars
-                    .render("service_unavailable", &NoHandlebarsData {})
-                    .unwrap();
-                (StatusCode::SERVICE_UNAVAILABLE, Html::from(body)).into_response()
-            }
-        })?;
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
 
-        let tz = query.get("tz").and_then(|o| TimeZone::from_posix_tz(o).ok()).unwrap_or(TimeZone::utc());
-        let offset = UtcOffset::from_whole_seconds(tz.find_current_local_time_type().unwrap().ut_offset()).unwrap();
+    response
+}
 
-        let now = OffsetDateTime::now_utc().to_offset(offset);
+async fn render_birthday_html(
+    state: &Arc<AppState<'_>>,
+    headers: &HeaderMap,
+    username: &str,
+    query: &HashMap<String, String>,
+) -> Result<Response, AppError> {
+    let offset = tz_offset(query)?;
+    let favorites = fetch_characters(state, username).await?;
 
-        characters.sort_by_upcoming(&now);
+    let now = OffsetDateTime::now_utc().to_offset(offset);
 
-        if !cache_hit {
-            state
-                .cache
-                .insert(username.to_string(), characters.clone())
-                .await;
-        }
+    let cal: BirthdayHtml = {
+        let mut characters = favorites.characters.clone();
+
+        characters.sort_by_upcoming(&now);
 
         let categories = characters.into_birthday_categories(&now);
 
-        BirthdayHtml::new(username, categories, &now)
-            .map_err(|_| render_internal_server_error(&state))?
+        let mut calendar_url = match &state.public_url {
+            Some(public_url) => public_url.clone(),
+            None => base_url(headers).map_err(|_| AppError::bad_request("Invalid Host header"))?,
+        };
+        set_user_path(&mut calendar_url, username, Some("calendar.ics"));
+
+        if let Some(tz) = query.get("tz") {
+            calendar_url.query_pairs_mut().append_pair("tz", tz);
+        }
+
+        BirthdayHtml::new(username, &calendar_url, favorites.stale, categories, &now)
+            .map_err(|_| AppError::internal_error())?
     };
 
     let body = state
         .handlebars
         .render("calendar", &to_json(cal))
-        .map_err(|_| render_internal_server_error(&state))?;
+        .map_err(|_| AppError::internal_error())?;
+
+    Ok(conditional_response(
+        headers,
+        &favorites.freshness(&now, state.cache.ttl()),
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        body,
+    ))
+}
 
-    Ok((Html::from(body)).into_response())
+/// Redirect the old `/ics?username=` form to the user's calendar file.
+async fn redirect_birthday_ics(RawQuery(query): RawQuery) -> Result<Redirect, StatusCode> {
+    redirect_to_user_path(query, Some("calendar.ics"))
 }
 
 async fn get_birthday_ics(
     State(state): State<Arc<AppState<'_>>>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
     Query(query): Query<HashMap<String, String>>,
-) -> Result<Response, Response> {
-    let cal: String = {
-        let username = query
-            .get("username")
-            .ok_or(StatusCode::UNPROCESSABLE_ENTITY.into_response())?;
-
-        if username.is_empty() {
-            return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
-        }
+) -> Response {
+    let format = match query.get("format") {
+        Some(format) => match format.parse::<CalendarFormat>() {
+            Ok(format) => format,
+            Err(_) => {
+                return AppError::invalid_parameter("format", "expected ics, jcal, or xcal")
+                    .into_response()
+            }
+        },
+        None => match negotiate(&headers, Representation::Calendar(CalendarFormat::default())) {
+            Some(Representation::Calendar(format)) => format,
+            _ => CalendarFormat::default(),
+        },
+    };
 
-        let cache_result = state.cache.get(username).await;
-        let cache_hit = cache_result.is_some();
+    // Browsers get error pages, and calendar clients get errors they can show.
+    let error_representation = match negotiate(&headers, Representation::Calendar(format)) {
+        Some(Representation::Html) => Representation::Html,
+        _ => Representation::Calendar(format),
+    };
 
-        let mut characters = if let Some(characters) = cache_result {
-            Ok(characters)
-        } else {
-            state
-                .circuit_breaker
-                .call_with(should_melt, crate::get_waifu_birthdays(&username))
-                .await
-        }
-        .map_err(|_| {
-            let body = state
-                .handlebars
-                .render("user_not_found", &NoHandlebarsData {})
-                .unwrap();
-            (StatusCode::NOT_FOUND, Html::from(body)).into_response()
-        })?;
-
-        if !cache_hit {
-            state
-                .cache
-                .insert(username.to_string(), characters.clone())
-                .await;
-        }
+    let mut response = render_birthday_calendar(&state, &headers, &username, &query, format)
+        .await
+        .map_err(|e| e.represented_as(error_representation))
+        .into_response();
+
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
 
-        let tz = query.get("tz").and_then(|o| TimeZone::from_posix_tz(o).ok()).unwrap_or(TimeZone::utc());
-        let offset = UtcOffset::from_whole_seconds(tz.find_current_local_time_type().unwrap().ut_offset()).unwrap();
+    response
+}
+
+async fn render_birthday_calendar(
+    state: &Arc<AppState<'_>>,
+    headers: &HeaderMap,
+    username: &str,
+    query: &HashMap<String, String>,
+    format: CalendarFormat,
+) -> Result<Response, AppError> {
+    let favorites = fetch_characters(state, username).await?;
+
+    let (cal, freshness) = {
+        let tz_name = query.get("tz").filter(|o| tzdb::tz_by_name(o).is_some());
+        let offset = match query.get("tz") {
+            Some(tz) => api::parse_tz(tz)?,
+            None => UtcOffset::UTC,
+        };
+
+        let color = query
+            .get("color")
+            .filter(|c| !c.is_empty() && c.chars().all(|ch| ch.is_ascii_alphabetic()))
+            .map(|c| c.to_string())
+            .unwrap_or(DEFAULT_CALENDAR_COLOR.to_string());
+
+        let event_style = query
+            .get("style")
+            .map(|style| style.parse::<EventStyle>())
+            .transpose()
+            .map_err(|_| {
+                AppError::invalid_parameter("style", "expected all-day, timed, or a time like 09:00")
+            })?
+            .unwrap_or_default();
 
         let now = OffsetDateTime::now_utc().to_offset(offset);
+
+        let horizon = {
+            let years = query
+                .get("years")
+                .map(|years| years.parse::<u32>())
+                .transpose()
+                .map_err(|_| AppError::invalid_parameter("years", "expected a whole number"))?;
+            let from = query
+                .get("from")
+                .map(|from| parse_date(from))
+                .transpose()
+                .map_err(|_| AppError::invalid_parameter("from", "expected a date like 2024-01-13"))?;
+            let to = query
+                .get("to")
+                .map(|to| parse_date(to))
+                .transpose()
+                .map_err(|_| AppError::invalid_parameter("to", "expected a date like 2024-01-13"))?;
+
+            Horizon::from_params(&now.date(), years, from, to)
+                .map_err(|e| AppError::invalid_parameter("horizon", &e.to_string()))?
+        };
+
+        let options = CalendarOptions {
+            timezone: tz_name.map(|o| o.to_string()),
+            event_style,
+            horizon,
+            refresh_interval: Some(Duration::seconds(state.cache.ttl().as_secs() as i64)),
+            color: Some(color),
+            last_modified: Some(favorites.fetched_at.to_offset(now.offset())),
+            ..CalendarOptions::for_user(username)
+        };
+
+        let mut characters = favorites.characters.clone();
         characters.sort_by_upcoming(&now);
-        characters
-            .to_ics(&now)
-            .map_err(|_| render_internal_server_error(&state))?
+        let cal = characters
+            .to_format_with_options(format, &now, &options)
+            .map_err(|_| AppError::internal_error())?;
+
+        (cal, favorites.freshness(&now, state.cache.ttl()))
     };
 
-    Ok((
+    Ok(conditional_response(
+        headers,
+        &freshness,
         [
             (
                 header::CONTENT_DISPOSITION,
-                "attachment; filename=\"birthdays.ics\"",
+                format!("attachment; filename=\"birthdays.{}\"", format.extension()),
             ),
-            (header::CONTENT_TYPE, "text/calendar"),
+            (header::CONTENT_TYPE, format.content_type().to_string()),
         ],
         cal,
+    ))
+}
+
+async fn get_birthday_vcf(
+    State(state): State<Arc<AppState<'_>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let username = query
+        .get("username")
+        .filter(|username| !username.is_empty())
+        .ok_or_else(|| AppError::invalid_parameter("username", "a username is required"))?;
+
+    render_birthday_vcf(&state, username, &query).await
+}
+
+async fn render_birthday_vcf(
+    state: &Arc<AppState<'_>>,
+    username: &str,
+    query: &HashMap<String, String>,
+) -> Result<Response, AppError> {
+    let version = query
+        .get("version")
+        .map(|version| version.parse::<VCardVersion>())
+        .transpose()
+        .map_err(|_| AppError::invalid_parameter("version", "expected 3.0 or 4.0"))?
+        .unwrap_or_default();
+
+    let characters = fetch_characters(state, username).await?.characters;
+
+    Ok((
+        [
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"birthdays.vcf\"",
+            ),
+            (header::CONTENT_TYPE, "text/vcard"),
+        ],
+        characters.to_vcf(version),
     )
         .into_response())
 }
 
-fn render_internal_server_error(state: &Arc<AppState<'_>>) -> Response {
-    let body = state
-        .handlebars
-        .render("internal_server_error", &NoHandlebarsData {})
-        .unwrap();
-    (StatusCode::INTERNAL_SERVER_ERROR, Html::from(body)).into_response()
+async fn get_birthday_export(
+    State(state): State<Arc<AppState<'_>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let username = query
+        .get("username")
+        .filter(|username| !username.is_empty())
+        .ok_or_else(|| AppError::invalid_parameter("username", "a username is required"))?;
+
+    let format = query
+        .get("format")
+        .ok_or_else(|| AppError::invalid_parameter("format", "an export format is required"))?
+        .parse::<ExportFormat>()
+        .map_err(|_| AppError::invalid_parameter("format", "unknown export format"))?;
+
+    let offset = tz_offset(&query)?;
+
+    let mut characters = fetch_characters(&state, username).await?.characters;
+
+    let now = OffsetDateTime::now_utc().to_offset(offset);
+    characters.sort_by_upcoming(&now);
+
+    let body = characters
+        .export(format, &now)
+        .map_err(|_| AppError::internal_error())?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"birthdays.{}\"", format.extension()),
+            ),
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Get the current offset of the zone named by the `tz` parameter, or UTC if there isn't one.
+fn tz_offset(query: &HashMap<String, String>) -> Result<UtcOffset, AppError> {
+    match query.get("tz") {
+        Some(tz) => api::parse_tz(tz),
+        None => Ok(UtcOffset::UTC),
+    }
+}
+
+async fn get_birthday_atom(
+    State(state): State<Arc<AppState<'_>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    get_birthday_feed(state, query, FeedKind::Atom).await
+}
+
+async fn get_birthday_rss(
+    State(state): State<Arc<AppState<'_>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    get_birthday_feed(state, query, FeedKind::Rss).await
+}
+
+enum FeedKind {
+    Atom,
+    Rss,
+}
+
+async fn get_birthday_feed(
+    state: Arc<AppState<'_>>,
+    query: HashMap<String, String>,
+    kind: FeedKind,
+) -> Result<Response, AppError> {
+    let username = query
+        .get("username")
+        .filter(|username| !username.is_empty())
+        .ok_or_else(|| AppError::invalid_parameter("username", "a username is required"))?;
+
+    let mut options = FeedOptions::for_user(username);
+
+    if let Some(days) = query.get("days") {
+        options.days = days
+            .parse::<u16>()
+            .ok()
+            .filter(|days| (1..=366).contains(days))
+            .ok_or_else(|| AppError::invalid_parameter("days", "expected 1 to 366 days"))?;
+    }
+
+    options.digest = query.get("digest").is_some_and(|digest| digest == "true");
+    options.time_zone = query.get("tz").map(|tz| api::parse_zone(tz)).transpose()?;
+
+    let offset = tz_offset(&query)?;
+    let characters = fetch_characters(&state, username).await?.characters;
+
+    let now = OffsetDateTime::now_utc().to_offset(offset);
+
+    let (body, content_type) = match kind {
+        FeedKind::Atom => (characters.to_atom(&now, &options), "application/atom+xml"),
+        FeedKind::Rss => (characters.to_rss(&now, &options), "application/rss+xml"),
+    };
+
+    let body = body.map_err(|_| AppError::internal_error())?;
+
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+/// Get a user's favorite characters from the cache, or from AniList on a miss.
+async fn fetch_characters(
+    state: &Arc<AppState<'_>>,
+    username: &str,
+) -> Result<Favorites, FetchError> {
+    Span::current().record("username", username);
+
+    state
+        .cache
+        .get_or_fetch(username, fetch_from_anilist(state, username))
+        .await
+}
+
+/// Fetch a user's favorite characters from AniList, through the circuit breaker.
+fn fetch_from_anilist(
+    state: &AppState<'_>,
+    username: &str,
+) -> impl Future<Output = Result<Vec<Character>, recloser::Error<anyhow::Error>>> + Send + 'static
+{
+    let circuit_breaker = state.circuit_breaker.clone();
+    let endpoint = state.anilist_endpoint.clone();
+    let metrics = state.metrics.clone();
+    let upstream = state.upstream.clone();
+    let owned_username = username.to_string();
+    let request_span = Span::current();
+
+    async move {
+        let span = info_span!(
+            parent: &request_span,
+            "anilist_fetch",
+            username = %owned_username,
+            pages = field::Empty,
+        );
+        let _in_flight = metrics.start_anilist_fetch();
+        let mut pages = 0;
+
+        let result = circuit_breaker
+            .call_with(
+                should_melt,
+                crate::get_waifu_birthdays_observed(&endpoint, &owned_username, |outcome| {
+                    metrics.record_anilist_request(outcome);
+                    pages += 1;
+                    debug!(?outcome, page = pages, "Requested favorites from AniList");
+                }),
+            )
+            .instrument(span.clone())
+            .await;
+
+        span.record("pages", pages);
+        request_span.record("pages", pages);
+
+        if result.is_ok() {
+            metrics.record_anilist_pages(pages);
+            upstream.record_success();
+        }
+
+        let rejected = matches!(result, Err(recloser::Error::Rejected));
+        if let Some(open) = upstream.record_call(rejected) {
+            metrics.record_breaker_transition(open);
+        }
+
+        result
+    }
+}
+
+/// Redirect a query-string route to its path-based equivalent under `/u/{username}`,
+/// carrying over every parameter except `username`.
+fn redirect_to_user_path(query: Option<String>, file: Option<&str>) -> Result<Redirect, StatusCode> {
+    let mut url = Url::parse("http://localhost/").expect("static URL is valid");
+    url.set_query(query.as_deref());
+
+    let mut username = None;
+    let mut params = vec![];
+
+    for (key, value) in url.query_pairs() {
+        if key == "username" {
+            username = Some(value.into_owned());
+        } else {
+            params.push((key.into_owned(), value.into_owned()));
+        }
+    }
+
+    let username = username
+        .filter(|username| !username.is_empty())
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    set_user_path(&mut url, &username, file);
+    url.set_query(None);
+
+    if !params.is_empty() {
+        url.query_pairs_mut().extend_pairs(params);
+    }
+
+    let location = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    Ok(Redirect::permanent(&location))
+}
+
+/// Point a base URL at `u/{username}` beneath it, or at a file beneath that.
+fn set_user_path(url: &mut Url, username: &str, file: Option<&str>) {
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().push("u").push(username);
+
+        if let Some(file) = file {
+            segments.push(file);
+        }
+    }
+}
+
+/// Get the URL this server was reached at, from the `Host` and `X-Forwarded-Proto` headers.
+fn base_url(headers: &HeaderMap) -> Result<Url> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .filter(|proto| *proto == "http" || *proto == "https")
+        .unwrap_or("http");
+
+    Ok(Url::parse(&format!("{}://{}/", scheme, host))?)
+}
+
+/// Turn an HTTP(S) calendar URL into a `webcal://` subscription link.
+fn webcal_url(url: &Url) -> String {
+    let url = url.as_str();
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+
+    format!("webcal://{}", rest)
 }
 
 fn should_melt(err: &anyhow::Error) -> bool {
     let cast_err = err.downcast_ref::<crate::Error>();
-    match cast_err {
-        Some(crate::Error::UserNotFound(_)) => false,
-        _ => true,
+    !matches!(cast_err, Some(crate::Error::UserNotFound(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use axum::{
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+    };
+    use reqwest::Url;
+    use time::UtcOffset;
+
+    use super::{
+        assets::Assets,
+        cache::FavoritesCache,
+        config::{CacheConfig, CircuitBreakerConfig},
+        drain, redirect_to_user_path, render_birthday_html, set_user_path, tz_offset,
+        webcal_url, AppState,
+    };
+
+    fn location(query: &str, file: Option<&str>) -> String {
+        let response = redirect_to_user_path(Some(query.to_string()), file)
+            .unwrap()
+            .into_response();
+
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn redirect_to_user_page() {
+        assert_eq!(
+            location("username=Owldown&tz=America%2FDenver", None),
+            "/u/Owldown?tz=America%2FDenver"
+        );
+    }
+
+    #[test]
+    fn redirect_to_user_calendar() {
+        assert_eq!(
+            location("tz=UTC&username=Owldown&style=timed", Some("calendar.ics")),
+            "/u/Owldown/calendar.ics?tz=UTC&style=timed"
+        );
+    }
+
+    #[test]
+    fn redirect_without_username() {
+        assert!(redirect_to_user_path(Some("tz=UTC".to_string()), None).is_err());
+        assert!(redirect_to_user_path(Some("username=".to_string()), None).is_err());
+        assert!(redirect_to_user_path(None, None).is_err());
+    }
+
+    #[test]
+    fn webcal_from_https() {
+        let url = Url::parse("https://waifu-calendar.fly.dev/u/Owldown/calendar.ics").unwrap();
+
+        assert_eq!(
+            webcal_url(&url),
+            "webcal://waifu-calendar.fly.dev/u/Owldown/calendar.ics"
+        );
+    }
+
+    #[test]
+    fn user_path_beneath_public_url() {
+        let mut url = Url::parse("https://example.com/waifu/").unwrap();
+        set_user_path(&mut url, "Owldown", Some("calendar.ics"));
+
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/waifu/u/Owldown/calendar.ics"
+        );
+    }
+
+    #[test]
+    fn tz_offset_rejects_unknown_zones() {
+        let query = |tz: &str| HashMap::from([("tz".to_string(), tz.to_string())]);
+
+        assert_eq!(tz_offset(&HashMap::new()).unwrap(), UtcOffset::UTC);
+        assert!(tz_offset(&query("Etc/GMT+5")).is_ok());
+        assert!(tz_offset(&query("Not/A_Zone")).is_err());
+    }
+
+    #[tokio::test]
+    async fn html_page_accepts_iana_zones() {
+        let cache = FavoritesCache::new(&CacheConfig::default());
+        cache
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+
+        let breaker = CircuitBreakerConfig::default();
+        let state = Arc::new(AppState::new(
+            cache,
+            Assets::Dir(env!("CARGO_MANIFEST_DIR").into())
+                .templates(false)
+                .unwrap(),
+            breaker.build(),
+            breaker.open_wait(),
+            crate::ANILIST_ENDPOINT.to_string(),
+            None,
+        ));
+        let render = |tz: &str| {
+            let state = state.clone();
+            let query = HashMap::from([("tz".to_string(), tz.to_string())]);
+
+            async move {
+                render_birthday_html(&state, &HeaderMap::new(), "Owldown", &query)
+                    .await
+                    .map(|response| response.status())
+                    .map_err(|err| err.into_response().status())
+            }
+        };
+
+        assert_eq!(render("America/Denver").await, Ok(StatusCode::OK));
+        assert_eq!(
+            render("Not/A_Zone").await,
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        );
+    }
+
+    #[tokio::test]
+    async fn drain_flushes_cache_after_timing_out() {
+        let cache = FavoritesCache::new(&CacheConfig::default());
+        cache
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+        cache.expire("Owldown").await;
+
+        let stale = cache
+            .get_or_fetch("Owldown", async {
+                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                Ok(vec![])
+            })
+            .await
+            .unwrap();
+        assert!(stale.stale);
+
+        // A request outlives the drain timeout, and the refresh finishes after it.
+        let mut servers = tokio::task::JoinSet::new();
+        servers.spawn(async {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            Ok(())
+        });
+
+        drain(servers, &cache, std::time::Duration::from_millis(200)).await;
+
+        let refreshed = cache
+            .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+            .await
+            .unwrap();
+        assert!(!refreshed.stale);
     }
 }
@@ -0,0 +1,375 @@
+//! The versioned JSON API.
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+use tz::TimeZone;
+
+use super::{
+    conditional::conditional_response, error::AppError, fetch_characters,
+    negotiate::Representation, AppState,
+};
+use crate::{export::ExportedCharacter, Characters};
+
+/// Which section of the calendar page a birthday falls under.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Bucket {
+    Today,
+    WithinThirtyDays,
+    Future,
+}
+
+impl FromStr for Bucket {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "today" => Ok(Bucket::Today),
+            "within_thirty_days" => Ok(Bucket::WithinThirtyDays),
+            "future" => Ok(Bucket::Future),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BirthdayJson<'a> {
+    #[serde(flatten)]
+    character: ExportedCharacter<'a>,
+    bucket: Bucket,
+}
+
+#[derive(Serialize)]
+struct BirthdaysJson<'a> {
+    username: &'a str,
+    now: String,
+    birthdays: Vec<BirthdayJson<'a>>,
+}
+
+/// Look up the zone named by the `tz` parameter, given as an IANA name or a POSIX TZ string.
+pub(super) fn parse_zone(tz: &str) -> Result<TimeZone, AppError> {
+    match tzdb::tz_by_name(tz) {
+        Some(zone) => TimeZone::new(
+            zone.transitions().to_vec(),
+            zone.local_time_types().to_vec(),
+            zone.leap_seconds().to_vec(),
+            *zone.extra_rule(),
+        )
+        .map_err(|_| AppError::internal_error()),
+        None => TimeZone::from_posix_tz(tz)
+            .map_err(|_| AppError::invalid_parameter("tz", "unknown time zone")),
+    }
+}
+
+/// Get the current offset of the zone named by the `tz` parameter,
+/// given as an IANA name or a POSIX TZ string.
+pub(super) fn parse_tz(tz: &str) -> Result<UtcOffset, AppError> {
+    let ut_offset = parse_zone(tz)?
+        .find_current_local_time_type()
+        .map(|t| t.ut_offset())
+        .map_err(|_| AppError::invalid_parameter("tz", "no current offset for time zone"))?;
+
+    UtcOffset::from_whole_seconds(ut_offset)
+        .map_err(|_| AppError::invalid_parameter("tz", "offset out of range"))
+}
+
+fn parse_param<T: FromStr>(
+    query: &HashMap<String, String>,
+    name: &str,
+    message: &str,
+) -> Result<Option<T>, AppError> {
+    query
+        .get(name)
+        .map(|value| value.parse::<T>())
+        .transpose()
+        .map_err(|_| AppError::invalid_parameter(name, message))
+}
+
+/// List a user's favorite characters by upcoming birthday.
+pub(super) async fn get_user_birthdays(
+    State(state): State<Arc<AppState<'_>>>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    user_birthdays(&state, &headers, &username, &query)
+        .await
+        .map_err(|e| e.represented_as(Representation::Json))
+}
+
+/// Render a user's birthdays as the JSON body of the birthdays API.
+pub(super) async fn user_birthdays(
+    state: &Arc<AppState<'_>>,
+    headers: &HeaderMap,
+    username: &str,
+    query: &HashMap<String, String>,
+) -> Result<Response, AppError> {
+    let offset = match query.get("tz") {
+        Some(tz) => parse_tz(tz)?,
+        None => UtcOffset::UTC,
+    };
+    let days: Option<u32> = parse_param(query, "days", "expected zero or more days")?;
+    let bucket: Option<Bucket> = parse_param(
+        query,
+        "bucket",
+        "expected today, within_thirty_days, or future",
+    )?;
+    let month: Option<u8> = parse_param(query, "month", "expected a month from 1 to 12")?;
+    let name = query.get("name").map(|name| name.to_lowercase());
+
+    if month.is_some_and(|month| !(1..=12).contains(&month)) {
+        return Err(AppError::invalid_parameter(
+            "month",
+            "expected a month from 1 to 12",
+        ));
+    }
+
+    let favorites = fetch_characters(state, username).await?;
+    let mut characters = favorites.characters.clone();
+
+    let now = OffsetDateTime::now_utc().to_offset(offset);
+    characters.sort_by_upcoming(&now);
+
+    let categories = characters.into_birthday_categories(&now);
+    let bucketed = [
+        (Bucket::Today, &categories.today),
+        (Bucket::WithinThirtyDays, &categories.within_thirty_days),
+        (Bucket::Future, &categories.future),
+    ];
+
+    let mut birthdays = vec![];
+
+    for (character_bucket, characters) in bucketed {
+        for character in characters {
+            let exported =
+                ExportedCharacter::new(character, &now).map_err(|_| AppError::internal_error())?;
+
+            let matches = bucket.is_none_or(|bucket| bucket == character_bucket)
+                && days.is_none_or(|days| exported.days_until <= i64::from(days))
+                && month.is_none_or(|month| character.birthday().month() as u8 == month)
+                && name
+                    .as_ref()
+                    .is_none_or(|name| character.name().to_lowercase().contains(name));
+
+            if matches {
+                birthdays.push(BirthdayJson {
+                    character: exported,
+                    bucket: character_bucket,
+                });
+            }
+        }
+    }
+
+    let body = BirthdaysJson {
+        username,
+        now: now.format(&Rfc3339).map_err(|_| AppError::internal_error())?,
+        birthdays,
+    };
+
+    let body = serde_json::to_string(&body).map_err(|_| AppError::internal_error())?;
+
+    Ok(conditional_response(
+        headers,
+        &favorites.freshness(&now, state.cache.ttl()),
+        [(header::CONTENT_TYPE, "application/json")],
+        body,
+    ))
+}
+
+/// Serve the OpenAPI document describing this API.
+pub(super) async fn get_openapi() -> Response {
+    Json(openapi()).into_response()
+}
+
+fn openapi() -> serde_json::Value {
+    let error_response = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": "#/components/schemas/Error" }
+                }
+            }
+        })
+    };
+
+    let retry_response = |description: &str| {
+        let mut response = error_response(description);
+        response["headers"] = json!({
+            "Retry-After": {
+                "description": "Seconds to wait before trying again",
+                "schema": { "type": "integer" }
+            }
+        });
+        response
+    };
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Waifu Calendar",
+            "description": "Birthdays of your favorite AniList characters.",
+            "version": "1.0.0",
+            "license": {
+                "name": "AGPL-3.0-or-later",
+                "identifier": "AGPL-3.0-or-later"
+            }
+        },
+        "paths": {
+            "/api/v1/users/{username}/birthdays": {
+                "get": {
+                    "summary": "List a user's favorite characters by upcoming birthday",
+                    "operationId": "getUserBirthdays",
+                    "parameters": [
+                        {
+                            "name": "username",
+                            "in": "path",
+                            "required": true,
+                            "description": "AniList username, case sensitive",
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "tz",
+                            "in": "query",
+                            "description": "IANA time zone or POSIX TZ string to count days in. Defaults to UTC.",
+                            "schema": { "type": "string", "example": "America/Denver" }
+                        },
+                        {
+                            "name": "days",
+                            "in": "query",
+                            "description": "Only include birthdays occurring within this many days",
+                            "schema": { "type": "integer", "minimum": 0 }
+                        },
+                        {
+                            "name": "bucket",
+                            "in": "query",
+                            "description": "Only include birthdays in this bucket",
+                            "schema": { "$ref": "#/components/schemas/Bucket" }
+                        },
+                        {
+                            "name": "month",
+                            "in": "query",
+                            "description": "Only include birthdays in this month",
+                            "schema": { "type": "integer", "minimum": 1, "maximum": 12 }
+                        },
+                        {
+                            "name": "name",
+                            "in": "query",
+                            "description": "Only include characters whose name contains this text, ignoring case",
+                            "schema": { "type": "string" }
+                        }
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The user's favorite characters, soonest birthday first",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/Birthdays" }
+                                }
+                            }
+                        },
+                        "404": error_response("The user was not found (user_not_found)"),
+                        "422": error_response("A query parameter was invalid (invalid_parameter)"),
+                        "429": retry_response("AniList is rate limiting requests (rate_limited)"),
+                        "500": error_response("Something went wrong (internal_error)"),
+                        "503": retry_response("AniList is unavailable (upstream_unavailable)")
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "Bucket": {
+                    "type": "string",
+                    "enum": ["today", "within_thirty_days", "future"]
+                },
+                "Birthday": {
+                    "type": "object",
+                    "required": [
+                        "name", "url", "image", "birthday", "next_occurrence",
+                        "days_until", "countdown", "bucket"
+                    ],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "url": { "type": "string", "format": "uri" },
+                        "image": { "type": ["string", "null"], "format": "uri" },
+                        "birthday": {
+                            "type": "object",
+                            "required": ["month", "day"],
+                            "properties": {
+                                "month": { "type": "integer", "minimum": 1, "maximum": 12 },
+                                "day": { "type": "integer", "minimum": 1, "maximum": 31 }
+                            }
+                        },
+                        "next_occurrence": { "type": "string", "format": "date" },
+                        "days_until": { "type": "integer", "minimum": 0 },
+                        "countdown": { "type": "string", "format": "duration" },
+                        "bucket": { "$ref": "#/components/schemas/Bucket" }
+                    }
+                },
+                "Birthdays": {
+                    "type": "object",
+                    "required": ["username", "now", "birthdays"],
+                    "properties": {
+                        "username": { "type": "string" },
+                        "now": { "type": "string", "format": "date-time" },
+                        "birthdays": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/Birthday" }
+                        }
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": {
+                        "error": {
+                            "type": "object",
+                            "required": ["code", "message"],
+                            "properties": {
+                                "code": {
+                                    "type": "string",
+                                    "enum": [
+                                        "invalid_parameter", "user_not_found", "rate_limited",
+                                        "internal_error", "upstream_unavailable"
+                                    ]
+                                },
+                                "message": { "type": "string" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{openapi, parse_param};
+
+    #[test]
+    fn openapi_describes_birthdays_route() {
+        let document = openapi();
+
+        assert!(document["paths"]["/api/v1/users/{username}/birthdays"]["get"].is_object());
+    }
+
+    #[test]
+    fn negative_days_are_invalid() {
+        let query = HashMap::from([("days".to_string(), "-1".to_string())]);
+
+        assert!(parse_param::<u32>(&query, "days", "expected zero or more days").is_err());
+    }
+}
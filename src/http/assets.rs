@@ -0,0 +1,287 @@
+//! The server's templates and static assets, built into it or read from a directory.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use axum::Router;
+use handlebars::{DirectorySourceOptions, Handlebars};
+use tower_http::services::ServeFile;
+
+use super::config::ServerConfig;
+
+/// A file served as it is.
+struct StaticFile {
+    route: &'static str,
+    /// The file's path within the assets directory.
+    path: &'static str,
+    #[cfg(feature = "embed-assets")]
+    content_type: &'static str,
+    #[cfg(feature = "embed-assets")]
+    contents: &'static [u8],
+    /// A hash of the contents, computed when the server is built.
+    #[cfg(feature = "embed-assets")]
+    hash: u64,
+}
+
+macro_rules! static_file {
+    ($route:literal, $path:literal, $content_type:literal) => {{
+        #[cfg(feature = "embed-assets")]
+        const CONTENTS: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path));
+
+        StaticFile {
+            route: $route,
+            path: $path,
+            #[cfg(feature = "embed-assets")]
+            content_type: $content_type,
+            #[cfg(feature = "embed-assets")]
+            contents: CONTENTS,
+            #[cfg(feature = "embed-assets")]
+            hash: super::conditional::fnv1a(CONTENTS),
+        }
+    }};
+}
+
+const STATIC_FILES: &[StaticFile] = &[
+    static_file!("/assets/pico.min.css", "assets/pico.min.css", "text/css"),
+    static_file!("/assets/frieren.jpg", "assets/frieren.jpg", "image/jpeg"),
+    static_file!(
+        "/humans.txt",
+        "assets/humans.txt",
+        "text/plain; charset=utf-8"
+    ),
+];
+
+#[cfg(feature = "embed-assets")]
+macro_rules! template {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/templates/",
+                $name,
+                ".hbs"
+            )),
+        )
+    };
+}
+
+/// Every template, by the name it's registered under.
+#[cfg(feature = "embed-assets")]
+const TEMPLATES: &[(&str, &str)] = &[
+    template!("calendar"),
+    template!("character_list"),
+    template!("character_list_today"),
+    template!("index"),
+    template!("internal_server_error"),
+    template!("layout"),
+    template!("service_unavailable"),
+    template!("too_many_requests"),
+    template!("user_not_found"),
+];
+
+#[cfg(feature = "embed-assets")]
+impl StaticFile {
+    /// Serve the built-in copy of this file, or confirm the client's copy is still current.
+    ///
+    /// Its URL doesn't change between versions, so clients cache it for a day,
+    /// then revalidate it by its ETag.
+    fn respond(&self, request: &axum::http::HeaderMap) -> axum::response::Response {
+        use axum::{
+            http::{header, StatusCode},
+            response::IntoResponse,
+        };
+
+        let etag = super::conditional::hash_etag(self.hash);
+        let headers = [
+            (header::ETAG, etag.clone()),
+            (header::CACHE_CONTROL, STATIC_CACHE_CONTROL.to_string()),
+        ];
+
+        if super::conditional::if_none_match(request, &etag) == Some(true) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+
+        (
+            headers,
+            [(header::CONTENT_TYPE, self.content_type)],
+            self.contents,
+        )
+            .into_response()
+    }
+}
+
+/// How long clients may use built-in static files before checking for new ones.
+#[cfg(feature = "embed-assets")]
+const STATIC_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Where the server's templates and assets come from.
+#[derive(Clone, Debug)]
+pub(super) enum Assets {
+    /// Built into the server.
+    #[cfg(feature = "embed-assets")]
+    Embedded,
+    /// A directory holding `templates` and `assets`.
+    Dir(PathBuf),
+}
+
+impl fmt::Display for Assets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "embed-assets")]
+            Assets::Embedded => write!(f, "the server binary"),
+            Assets::Dir(dir) => write!(f, "{:?}", dir),
+        }
+    }
+}
+
+impl Assets {
+    /// Use the configured directory, or the built-in assets unless templates
+    /// are to be reloaded from disk, or there are none built in.
+    pub fn new(config: &ServerConfig) -> Self {
+        match &config.assets {
+            Some(dir) => Assets::Dir(dir.clone()),
+            #[cfg(feature = "embed-assets")]
+            None if !config.dev_mode => Assets::Embedded,
+            None => Assets::Dir(PathBuf::from(".")),
+        }
+    }
+
+    /// The directory assets are read from, unless they're built in.
+    pub fn dir(&self) -> Option<&Path> {
+        match self {
+            #[cfg(feature = "embed-assets")]
+            Assets::Embedded => None,
+            Assets::Dir(dir) => Some(dir),
+        }
+    }
+
+    /// Load every template, to be reloaded from disk whenever it changes in `dev_mode`.
+    pub fn templates(&self, dev_mode: bool) -> Result<Handlebars<'static>> {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        handlebars.set_dev_mode(dev_mode);
+
+        match self {
+            #[cfg(feature = "embed-assets")]
+            Assets::Embedded => {
+                for (name, source) in TEMPLATES {
+                    handlebars.register_template_string(name, source)?;
+                }
+            }
+            Assets::Dir(dir) => {
+                handlebars.register_templates_directory(
+                    dir.join("templates"),
+                    DirectorySourceOptions::default(),
+                )?;
+            }
+        }
+
+        Ok(handlebars)
+    }
+
+    /// Add a route for every static asset.
+    pub fn serve<S>(&self, mut router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        for file in STATIC_FILES {
+            router = match self {
+                #[cfg(feature = "embed-assets")]
+                Assets::Embedded => router.route(
+                    file.route,
+                    axum::routing::get(move |headers: axum::http::HeaderMap| async move {
+                        file.respond(&headers)
+                    }),
+                ),
+                Assets::Dir(dir) => {
+                    router.route_service(file.route, ServeFile::new(dir.join(file.path)))
+                }
+            };
+        }
+
+        router
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Assets;
+
+    #[cfg(feature = "embed-assets")]
+    #[test]
+    fn embedded_templates_match_directory() {
+        let dir = Assets::Dir(env!("CARGO_MANIFEST_DIR").into());
+        let embedded = Assets::Embedded;
+
+        let mut from_dir: Vec<_> = dir
+            .templates(false)
+            .unwrap()
+            .get_templates()
+            .keys()
+            .cloned()
+            .collect();
+        let mut from_binary: Vec<_> = embedded
+            .templates(false)
+            .unwrap()
+            .get_templates()
+            .keys()
+            .cloned()
+            .collect();
+        from_dir.sort();
+        from_binary.sort();
+
+        assert_eq!(from_binary, from_dir);
+    }
+
+    #[cfg(feature = "embed-assets")]
+    #[test]
+    fn embedded_files_are_cached_and_revalidated() {
+        use axum::http::{header, HeaderMap, StatusCode};
+
+        let file = &super::STATIC_FILES[0];
+
+        let response = file.respond(&HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=86400"
+        );
+
+        let mut request = HeaderMap::new();
+        request.insert(
+            header::IF_NONE_MATCH,
+            response.headers()[header::ETAG].clone(),
+        );
+        assert_eq!(file.respond(&request).status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn dev_mode_reloads_changed_templates() {
+        let dir =
+            std::env::temp_dir().join(format!("waifu-calendar-assets-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("templates")).unwrap();
+        let template = dir.join("templates/greeting.hbs");
+        std::fs::write(&template, "Hello, {{name}}").unwrap();
+
+        let handlebars = Assets::Dir(dir.clone()).templates(true).unwrap();
+        let data = json!({ "name": "Frieren" });
+        assert_eq!(
+            handlebars.render("greeting", &data).unwrap(),
+            "Hello, Frieren"
+        );
+
+        std::fs::write(&template, "Goodbye, {{name}}").unwrap();
+        assert_eq!(
+            handlebars.render("greeting", &data).unwrap(),
+            "Goodbye, Frieren"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
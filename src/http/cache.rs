@@ -0,0 +1,668 @@
+//! Caching favorites fetched from AniList.
+
+mod disk;
+mod hits;
+mod metrics;
+mod redis;
+
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::Result;
+use moka::{future::Cache, Expiry};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, Time};
+use tokio::task::JoinSet;
+use tracing::{info, warn, Span};
+
+use self::{
+    disk::DiskStore,
+    hits::HitCounter,
+    metrics::{CacheMetrics, Lookup, Tier},
+    redis::RedisStore,
+};
+use super::{conditional::Freshness, config::CacheConfig};
+use crate::Character;
+
+/// How many cache TTLs a user's requests are counted over, to tell whether they're popular.
+const POPULAR_TTLS: u32 = 2;
+
+/// The error from fetching favorites, shared between every request waiting on the fetch.
+pub(super) type FetchError = Arc<recloser::Error<anyhow::Error>>;
+
+/// A user's favorite characters, and when they were fetched from AniList.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct Favorites {
+    pub characters: Vec<Character>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub fetched_at: OffsetDateTime,
+    /// Whether these favorites outlived the cache TTL, and are being refreshed.
+    #[serde(skip)]
+    pub stale: bool,
+}
+
+impl Favorites {
+    fn new(characters: Vec<Character>) -> Self {
+        Self {
+            characters,
+            fetched_at: OffsetDateTime::now_utc(),
+            stale: false,
+        }
+    }
+
+    /// How long ago these favorites were fetched.
+    fn age(&self) -> std::time::Duration {
+        (OffsetDateTime::now_utc() - self.fetched_at).unsigned_abs()
+    }
+
+    /// Get the freshness of a response rendered from these favorites at `now`,
+    /// given they are cached for `ttl`.
+    ///
+    /// Responses change at midnight as birthdays pass, and expire along with the cache entry.
+    pub fn freshness(&self, now: &OffsetDateTime, ttl: std::time::Duration) -> Freshness {
+        let midnight = now.replace_time(Time::MIDNIGHT);
+        let age = (*now - self.fetched_at).unsigned_abs();
+
+        Freshness {
+            last_modified: self.fetched_at.max(midnight),
+            max_age: ttl.saturating_sub(age),
+            stale_age: self.stale.then_some(age),
+        }
+    }
+}
+
+/// Where favorites are kept, besides in memory.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub(super) enum CacheBackend {
+    /// Only in memory, so they are lost on restart.
+    #[default]
+    Memory,
+    /// Also in a directory of JSON files, which are loaded back on start.
+    Disk(PathBuf),
+    /// Also in the Redis server at this URL, shared by every replica using it.
+    Redis(String),
+}
+
+/// Where favorites are kept besides in memory.
+enum Store {
+    Disk(DiskStore),
+    Redis(RedisStore),
+}
+
+impl Store {
+    async fn get(&self, username: &str) -> Result<Option<Favorites>> {
+        match self {
+            Store::Disk(store) => store.get(username).await,
+            Store::Redis(store) => store.get(username).await,
+        }
+    }
+
+    /// Save a user's favorites, to be kept for `ttl`.
+    async fn save(
+        &self,
+        username: &str,
+        favorites: &Favorites,
+        ttl: std::time::Duration,
+    ) -> Result<()> {
+        match self {
+            // Old files are removed when the store is next loaded instead.
+            Store::Disk(store) => store.save(username, favorites).await,
+            Store::Redis(store) => store.save(username, favorites, ttl).await,
+        }
+    }
+}
+
+/// Expire favorites a fixed time after they were fetched, rather than after they were cached,
+/// so that favorites loaded from disk or another replica keep their original expiry.
+struct ExpireAfterFetch(std::time::Duration);
+
+impl ExpireAfterFetch {
+    fn remaining(&self, favorites: &Favorites) -> std::time::Duration {
+        self.0.saturating_sub(favorites.age())
+    }
+}
+
+impl Expiry<String, Favorites> for ExpireAfterFetch {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &Favorites,
+        _created_at: Instant,
+    ) -> Option<std::time::Duration> {
+        Some(self.remaining(value))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &Favorites,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<std::time::Duration>,
+    ) -> Option<std::time::Duration> {
+        Some(self.remaining(value))
+    }
+}
+
+/// Favorites cached for a TTL, and kept for a grace period after that
+/// to serve while they are refreshed.
+#[derive(Clone)]
+pub(super) struct FavoritesCache {
+    fresh: Cache<String, Favorites>,
+    stale: Cache<String, Favorites>,
+    store: Option<Arc<Store>>,
+    ttl: std::time::Duration,
+    grace_period: std::time::Duration,
+    /// Requests for each user, unless favorites aren't refreshed in the background.
+    hits: Option<Arc<HitCounter>>,
+    /// Refreshes running in the background, to wait for before shutting down.
+    refreshes: Arc<Mutex<JoinSet<()>>>,
+    metrics: CacheMetrics,
+}
+
+impl FavoritesCache {
+    /// Build a cache that only keeps favorites in memory.
+    pub fn new(config: &CacheConfig) -> Self {
+        let (ttl, grace_period) = (config.ttl(), config.stale_grace_period());
+        let metrics = CacheMetrics::default();
+
+        Self {
+            fresh: Self::build(ttl, config.capacity, Tier::Fresh, &metrics),
+            stale: Self::build(ttl + grace_period, config.capacity, Tier::Stale, &metrics),
+            store: None,
+            ttl,
+            grace_period,
+            hits: (config.refresh_budget > 0)
+                .then(|| Arc::new(HitCounter::new(ttl * POPULAR_TTLS))),
+            refreshes: Arc::default(),
+            metrics,
+        }
+    }
+
+    /// Build a cache kept in the configured backend, loading any favorites saved on disk.
+    ///
+    /// Favorites in Redis aren't loaded up front, but looked up on each miss.
+    pub async fn open(config: &CacheConfig) -> Result<Self> {
+        let mut cache = Self::new(config);
+
+        match &config.backend() {
+            CacheBackend::Memory => {}
+            CacheBackend::Disk(dir) => {
+                let store = DiskStore::open(dir)?;
+                let loaded = store.load(cache.ttl + cache.grace_period)?;
+
+                info!("Loaded {} users' favorites from {:?}", loaded.len(), dir);
+
+                for (username, favorites) in loaded {
+                    cache.fresh.insert(username.clone(), favorites.clone()).await;
+                    cache.stale.insert(username, favorites).await;
+                }
+
+                cache.store = Some(Arc::new(Store::Disk(store)));
+            }
+            CacheBackend::Redis(url) => {
+                let store = RedisStore::open(url).await?;
+
+                info!("Sharing favorites through Redis at {:?}", url);
+
+                cache.store = Some(Arc::new(Store::Redis(store)));
+            }
+        }
+
+        Ok(cache)
+    }
+
+    fn build(
+        ttl: std::time::Duration,
+        capacity: u64,
+        tier: Tier,
+        metrics: &CacheMetrics,
+    ) -> Cache<String, Favorites> {
+        let metrics = metrics.clone();
+
+        Cache::builder()
+            .weigher(|_key, value: &Favorites| -> u32 {
+                value.characters.len().try_into().unwrap_or(u32::MAX)
+            })
+            .max_capacity(capacity)
+            .expire_after(ExpireAfterFetch(ttl))
+            .eviction_listener(move |_key, _value, cause| metrics.record_removal(tier, cause))
+            .build()
+    }
+
+    /// How long fetched favorites stay in the cache.
+    pub fn ttl(&self) -> std::time::Duration {
+        self.ttl
+    }
+
+    /// Register metrics about lookups, evictions, and the size of the cache.
+    pub fn register_metrics(&self, registry: &mut prometheus_client::registry::Registry) {
+        self.metrics.register(registry);
+    }
+
+    /// Bring the metrics about the size of the cache up to date.
+    pub async fn update_size_metrics(&self) {
+        self.metrics.update_size(Tier::Fresh, &self.fresh).await;
+        self.metrics.update_size(Tier::Stale, &self.stale).await;
+    }
+
+    /// Get a user's favorites from the cache, or populate it by running `fetch` on a miss.
+    ///
+    /// If the favorites have expired but are still within the grace period,
+    /// they are returned marked stale, and `fetch` runs in the background instead.
+    ///
+    /// Only users whose favorites were found count as requested,
+    /// so users that don't exist aren't refreshed.
+    pub async fn get_or_fetch<F>(&self, username: &str, fetch: F) -> Result<Favorites, FetchError>
+    where
+        F: Future<Output = Result<Vec<Character>, recloser::Error<anyhow::Error>>>
+            + Send
+            + 'static,
+    {
+        let favorites = self.lookup(username, fetch).await?;
+        if let Some(hits) = &self.hits {
+            hits.record(username);
+        }
+
+        Ok(favorites)
+    }
+
+    async fn lookup<F>(&self, username: &str, fetch: F) -> Result<Favorites, FetchError>
+    where
+        F: Future<Output = Result<Vec<Character>, recloser::Error<anyhow::Error>>>
+            + Send
+            + 'static,
+    {
+        if let Some(favorites) = self.fresh.get(username).await {
+            self.record_lookup(Lookup::Fresh);
+            return Ok(favorites);
+        }
+
+        if let Some(favorites) = self.stale.get(username).await {
+            self.record_lookup(Lookup::Stale);
+
+            let cache = self.clone();
+            let username = username.to_string();
+
+            let mut refreshes = self.refreshes.lock().unwrap_or_else(|err| err.into_inner());
+
+            // Forget refreshes that already finished, so they don't pile up.
+            while refreshes.try_join_next().is_some() {}
+
+            refreshes.spawn(async move {
+                if let Err(err) = cache.fetch(&username, fetch).await {
+                    warn!("Failed to refresh favorites for {}: {:?}", username, err);
+                }
+            });
+
+            return Ok(Favorites {
+                stale: true,
+                ..favorites
+            });
+        }
+
+        self.record_lookup(Lookup::Miss);
+        self.fetch(username, fetch).await
+    }
+
+    /// Count a lookup, and note on the current request's span how fresh the favorites were.
+    fn record_lookup(&self, lookup: Lookup) {
+        self.metrics.record_lookup(lookup);
+        Span::current().record("cache", lookup.label());
+    }
+
+    /// Wait for every refresh running in the background to finish, and save its result.
+    pub async fn flush(&self) {
+        let mut refreshes = std::mem::take(
+            &mut *self.refreshes.lock().unwrap_or_else(|err| err.into_inner()),
+        );
+
+        while refreshes.join_next().await.is_some() {}
+    }
+
+    /// Run `fetch` and cache its result, unless the store already has fresh favorites.
+    ///
+    /// Concurrent fetches for the same username wait on a single fetch and share its result.
+    /// Failed fetches are not cached.
+    async fn fetch<F>(&self, username: &str, fetch: F) -> Result<Favorites, FetchError>
+    where
+        F: Future<Output = Result<Vec<Character>, recloser::Error<anyhow::Error>>>,
+    {
+        self.fresh
+            .try_get_with_by_ref(username, async {
+                if let Some(favorites) = self.get_stored(username).await {
+                    self.stale
+                        .insert(username.to_string(), favorites.clone())
+                        .await;
+                    return Ok(favorites);
+                }
+
+                let favorites = Favorites::new(fetch.await?);
+                self.keep(username, &favorites).await;
+
+                Ok(favorites)
+            })
+            .await
+    }
+
+    /// Keep newly fetched favorites through the grace period, and save them to the store.
+    async fn keep(&self, username: &str, favorites: &Favorites) {
+        self.stale
+            .insert(username.to_string(), favorites.clone())
+            .await;
+
+        if let Some(store) = &self.store {
+            let ttl = (self.ttl + self.grace_period).saturating_sub(favorites.age());
+
+            if let Err(err) = store.save(username, favorites, ttl).await {
+                warn!("Failed to save favorites for {}: {:?}", username, err);
+            }
+        }
+    }
+
+    /// List users requested at least `min_hits` times within the last [`POPULAR_TTLS`] TTLs,
+    /// most requested first.
+    pub fn popular_usernames(&self, min_hits: u64) -> Vec<String> {
+        self.hits
+            .as_ref()
+            .map(|hits| hits.hot(min_hits))
+            .unwrap_or_default()
+    }
+
+    /// Check whether a user's cached favorites expire within `ahead`, or already have
+    /// but are still within the grace period.
+    ///
+    /// Users with nothing cached don't count, since there's nothing to refresh.
+    pub async fn expires_within(&self, username: &str, ahead: std::time::Duration) -> bool {
+        match self.fresh.get(username).await {
+            Some(favorites) => favorites.age() + ahead >= self.ttl,
+            None => self.stale.contains_key(username),
+        }
+    }
+
+    /// Expire a user's favorites as if their TTL had passed, keeping them through the grace period.
+    #[cfg(test)]
+    pub async fn expire(&self, username: &str) {
+        self.fresh.invalidate(username).await;
+    }
+
+    /// Replace a user's cached favorites with the result of `fetch`, before they expire.
+    ///
+    /// If the store holds newer favorites than the cache, such as ones another replica
+    /// already refreshed, those are used instead and `fetch` isn't run.
+    pub async fn refresh<F>(&self, username: &str, fetch: F) -> Result<(), FetchError>
+    where
+        F: Future<Output = Result<Vec<Character>, recloser::Error<anyhow::Error>>>,
+    {
+        let cached_at = self
+            .stale
+            .get(username)
+            .await
+            .map(|favorites| favorites.fetched_at);
+
+        if let Some(favorites) = self.get_stored(username).await {
+            if cached_at.is_none_or(|cached_at| favorites.fetched_at > cached_at) {
+                self.fresh
+                    .insert(username.to_string(), favorites.clone())
+                    .await;
+                self.stale.insert(username.to_string(), favorites).await;
+                return Ok(());
+            }
+        }
+
+        let favorites = Favorites::new(fetch.await.map_err(Arc::new)?);
+
+        self.fresh
+            .insert(username.to_string(), favorites.clone())
+            .await;
+        self.keep(username, &favorites).await;
+
+        Ok(())
+    }
+
+    /// Get a user's favorites from the store, if they were fetched within the TTL,
+    /// such as by another replica.
+    ///
+    /// Errors reading the store are logged, so AniList is asked instead.
+    async fn get_stored(&self, username: &str) -> Option<Favorites> {
+        match self.store.as_ref()?.get(username).await {
+            Ok(favorites) => favorites.filter(|favorites| favorites.age() < self.ttl),
+            Err(err) => {
+                warn!("Failed to read stored favorites for {}: {:?}", username, err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::FavoritesCache;
+    use crate::http::config::{CacheBackendKind, CacheConfig};
+
+    fn config() -> CacheConfig {
+        CacheConfig {
+            stale_grace_period_secs: 60,
+            ..CacheConfig::default()
+        }
+    }
+
+    fn disk_config(dir: &std::path::Path) -> CacheConfig {
+        CacheConfig {
+            backend: CacheBackendKind::Disk,
+            dir: dir.to_path_buf(),
+            ..config()
+        }
+    }
+
+    fn cache() -> FavoritesCache {
+        FavoritesCache::new(&config())
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_fetch() {
+        let cache = cache();
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..10).map(|_| {
+            let cache = cache.clone();
+            let fetches = fetches.clone();
+
+            tokio::spawn(async move {
+                cache
+                    .get_or_fetch("Owldown", async move {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok(vec![])
+                    })
+                    .await
+            })
+        });
+
+        for task in tasks.collect::<Vec<_>>() {
+            assert!(task.await.unwrap().is_ok());
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_are_not_cached() {
+        let cache = cache();
+
+        let failed = cache
+            .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+            .await;
+        assert!(failed.is_err());
+
+        let fetched = cache.get_or_fetch("Owldown", async { Ok(vec![]) }).await;
+        assert!(fetched.is_ok());
+    }
+
+    #[tokio::test]
+    async fn serves_stale_favorites_while_refreshing() {
+        let cache = cache();
+
+        let fetched = cache
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+        assert!(!fetched.stale);
+
+        cache.fresh.invalidate("Owldown").await;
+
+        let stale = cache
+            .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+            .await
+            .unwrap();
+        assert!(stale.stale);
+        assert_eq!(stale.fetched_at, fetched.fetched_at);
+    }
+
+    #[tokio::test]
+    async fn flush_waits_for_background_refreshes() {
+        let cache = cache();
+
+        let fetched = cache
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+
+        cache.fresh.invalidate("Owldown").await;
+
+        let stale = cache
+            .get_or_fetch("Owldown", async {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                Ok(vec![])
+            })
+            .await
+            .unwrap();
+        assert!(stale.stale);
+
+        cache.flush().await;
+
+        let refreshed = cache.fresh.get("Owldown").await.unwrap();
+        assert!(refreshed.fetched_at > fetched.fetched_at);
+    }
+
+    #[tokio::test]
+    async fn refresh_replaces_cached_favorites() {
+        let cache = cache();
+
+        let fetched = cache
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+        assert!(
+            !cache
+                .expires_within("Owldown", std::time::Duration::from_secs(60))
+                .await
+        );
+
+        cache
+            .refresh("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+
+        let refreshed = cache
+            .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+            .await
+            .unwrap();
+        assert!(refreshed.fetched_at > fetched.fetched_at);
+        assert_eq!(
+            cache.popular_usernames(2),
+            vec!["Owldown".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_are_not_popular() {
+        let cache = cache();
+
+        for _ in 0..2 {
+            let _ = cache
+                .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+                .await;
+        }
+
+        assert!(cache.popular_usernames(1).is_empty());
+        assert!(
+            !cache
+                .expires_within("Owldown", std::time::Duration::from_secs(60))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_are_not_counted_without_refreshing() {
+        let cache = FavoritesCache::new(&CacheConfig {
+            refresh_budget: 0,
+            ..config()
+        });
+
+        for _ in 0..2 {
+            cache
+                .get_or_fetch("Owldown", async { Ok(vec![]) })
+                .await
+                .unwrap();
+        }
+
+        assert!(cache.popular_usernames(1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn open_loads_favorites_from_disk() {
+        let dir = std::env::temp_dir().join(format!("waifu-calendar-open-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = disk_config(&dir);
+
+        let first = FavoritesCache::open(&config).await.unwrap();
+        let fetched = first
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+
+        let second = FavoritesCache::open(&config).await.unwrap();
+        let loaded = second
+            .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+            .await
+            .unwrap();
+
+        assert!(!loaded.stale);
+        assert_eq!(loaded.fetched_at, fetched.fetched_at);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn misses_use_favorites_stored_by_another_cache() {
+        let dir =
+            std::env::temp_dir().join(format!("waifu-calendar-shared-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = disk_config(&dir);
+
+        let first = FavoritesCache::open(&config).await.unwrap();
+        let second = FavoritesCache::open(&config).await.unwrap();
+
+        let fetched = first
+            .get_or_fetch("Owldown", async { Ok(vec![]) })
+            .await
+            .unwrap();
+        let shared = second
+            .get_or_fetch("Owldown", async { Err(recloser::Error::Rejected) })
+            .await
+            .unwrap();
+
+        assert_eq!(shared.fetched_at, fetched.fetched_at);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Favorites persisted as JSON files, so the cache survives restarts.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use super::Favorites;
+
+/// A user's favorites as saved to disk.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    username: String,
+    #[serde(flatten)]
+    favorites: Favorites,
+}
+
+/// A directory holding one JSON file of favorites per user.
+#[derive(Debug)]
+pub(super) struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    /// Open a store in `dir`, creating the directory if needed.
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create cache directory {:?}", dir))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Get the file a user's favorites are saved in.
+    ///
+    /// Usernames are hex-encoded so that any username makes a safe file name.
+    fn path(&self, username: &str) -> PathBuf {
+        let name: String = username.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// Get a user's saved favorites, if there are any.
+    pub async fn get(&self, username: &str) -> Result<Option<Favorites>> {
+        let path = self.path(username);
+
+        let json = match tokio::fs::read(&path).await {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read cache file {:?}", path))
+            }
+        };
+
+        Ok(Some(serde_json::from_slice::<Record>(&json)?.favorites))
+    }
+
+    /// Save a user's favorites, replacing any saved before.
+    pub async fn save(&self, username: &str, favorites: &Favorites) -> Result<()> {
+        let record = Record {
+            username: username.to_string(),
+            favorites: favorites.clone(),
+        };
+        let json = serde_json::to_vec(&record)?;
+
+        let path = self.path(username);
+        let tmp_path = path.with_extension("json.tmp");
+
+        // Write to a temporary file first, so a crash can't leave a half-written record.
+        tokio::fs::write(&tmp_path, json)
+            .await
+            .with_context(|| format!("Failed to write cache file {:?}", tmp_path))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Failed to replace cache file {:?}", path))?;
+
+        Ok(())
+    }
+
+    /// Load every user's favorites fetched less than `max_age` ago.
+    ///
+    /// Older and unreadable records are deleted.
+    pub fn load(&self, max_age: std::time::Duration) -> Result<Vec<(String, Favorites)>> {
+        let now = OffsetDateTime::now_utc();
+        let mut loaded = vec![];
+
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read cache directory {:?}", self.dir))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let record = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_slice::<Record>(&json)?));
+
+            match record {
+                Ok(record) if (now - record.favorites.fetched_at).unsigned_abs() < max_age => {
+                    loaded.push((record.username, record.favorites));
+                }
+                Ok(_) => {
+                    let _ = fs::remove_file(&path);
+                }
+                Err(err) => {
+                    warn!("Removing unreadable cache file {:?}: {:?}", path, err);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, Month, OffsetDateTime};
+
+    use super::DiskStore;
+    use crate::{http::cache::Favorites, Birthday, Character};
+
+    fn favorites(fetched_at: OffsetDateTime) -> Favorites {
+        Favorites {
+            characters: vec![Character::new(
+                "Frieren",
+                "https://anilist.co/character/176754",
+                Birthday::new(Month::March, 8),
+            )],
+            fetched_at,
+            stale: false,
+        }
+    }
+
+    fn store(name: &str) -> DiskStore {
+        let dir = std::env::temp_dir().join(format!(
+            "waifu-calendar-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        DiskStore::open(&dir).unwrap()
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let store = store("save-and-load");
+        let saved = favorites(OffsetDateTime::now_utc().replace_nanosecond(0).unwrap());
+
+        store.save("Owl/down", &saved).await.unwrap();
+
+        let loaded = store.load(std::time::Duration::from_secs(60)).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, "Owl/down");
+        assert_eq!(loaded[0].1.characters, saved.characters);
+        assert_eq!(loaded[0].1.fetched_at, saved.fetched_at);
+
+        let got = store.get("Owl/down").await.unwrap().unwrap();
+        assert_eq!(got.fetched_at, saved.fetched_at);
+        assert!(store.get("Nobody").await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_removes_old_records() {
+        let store = store("load-removes-old-records");
+        let old = favorites(OffsetDateTime::now_utc() - Duration::hours(2));
+
+        store.save("Owldown", &old).await.unwrap();
+
+        assert!(store
+            .load(std::time::Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+        assert!(!store.path("Owldown").exists());
+
+        std::fs::remove_dir_all(&store.dir).unwrap();
+    }
+}
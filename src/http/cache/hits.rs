@@ -0,0 +1,153 @@
+//! Counting how often each user's favorites are requested, to find ones worth refreshing early.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How often a user's favorites were requested in the current window.
+struct Hits {
+    count: u64,
+    /// When the current window started, at the first request in it.
+    since: Instant,
+}
+
+impl Hits {
+    fn is_current(&self, window: Duration) -> bool {
+        self.since.elapsed() < window
+    }
+}
+
+struct Counts {
+    hits: HashMap<String, Hits>,
+    pruned_at: Instant,
+}
+
+/// Request counts of every user requested recently, counted over windows of a fixed length
+/// so a user's popularity reflects their recent traffic.
+pub(super) struct HitCounter {
+    window: Duration,
+    counts: Mutex<Counts>,
+}
+
+impl HitCounter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            counts: Mutex::new(Counts {
+                hits: HashMap::new(),
+                pruned_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Count a request for a user's favorites.
+    ///
+    /// Once a window, users whose window has ended are forgotten.
+    pub fn record(&self, username: &str) {
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+
+        if counts.pruned_at.elapsed() >= self.window {
+            counts
+                .hits
+                .retain(|_, user_hits| user_hits.is_current(self.window));
+            counts.pruned_at = now;
+        }
+
+        match counts.hits.get_mut(username) {
+            Some(user_hits) if user_hits.is_current(self.window) => user_hits.count += 1,
+            _ => {
+                counts.hits.insert(
+                    username.to_string(),
+                    Hits {
+                        count: 1,
+                        since: now,
+                    },
+                );
+            }
+        }
+    }
+
+    /// List users requested at least `min_hits` times in their current window,
+    /// most requested first.
+    pub fn hot(&self, min_hits: u64) -> Vec<String> {
+        let counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+
+        let mut hot = counts
+            .hits
+            .iter()
+            .filter(|(_, user_hits)| {
+                user_hits.is_current(self.window) && user_hits.count >= min_hits
+            })
+            .collect::<Vec<_>>();
+        hot.sort_by_key(|(_, user_hits)| std::cmp::Reverse(user_hits.count));
+
+        hot.into_iter()
+            .map(|(username, _)| username.clone())
+            .collect()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.counts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .hits
+            .len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::HitCounter;
+
+    #[test]
+    fn hot_users_are_listed_most_requested_first() {
+        let counter = HitCounter::new(Duration::from_secs(60));
+
+        counter.record("Once");
+        counter.record("Twice");
+        counter.record("Twice");
+        for _ in 0..3 {
+            counter.record("Thrice");
+        }
+
+        assert_eq!(
+            counter.hot(2),
+            vec!["Thrice".to_string(), "Twice".to_string()]
+        );
+    }
+
+    #[test]
+    fn counts_start_over_each_window() {
+        let counter = HitCounter::new(Duration::from_millis(20));
+
+        for _ in 0..5 {
+            counter.record("Owldown");
+        }
+        assert_eq!(counter.hot(2), vec!["Owldown".to_string()]);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(counter.hot(1).is_empty());
+
+        counter.record("Owldown");
+        assert!(counter.hot(2).is_empty());
+    }
+
+    #[test]
+    fn users_not_requested_recently_are_forgotten() {
+        let counter = HitCounter::new(Duration::from_millis(20));
+
+        counter.record("Owldown");
+        counter.record("Frieren");
+
+        std::thread::sleep(Duration::from_millis(30));
+        counter.record("Fern");
+
+        assert_eq!(counter.len(), 1);
+    }
+}
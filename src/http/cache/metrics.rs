@@ -0,0 +1,131 @@
+//! Metrics about how the cache is used.
+
+use moka::{future::Cache, notification::RemovalCause};
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+
+use super::Favorites;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, EncodeLabelSet)]
+struct LookupLabels {
+    result: &'static str,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, EncodeLabelSet)]
+struct TierLabels {
+    tier: &'static str,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, EncodeLabelSet)]
+struct EvictionLabels {
+    tier: &'static str,
+    cause: &'static str,
+}
+
+/// Which of the cache's two tiers an entry is in.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Tier {
+    Fresh,
+    Stale,
+}
+
+impl Tier {
+    fn label(self) -> &'static str {
+        match self {
+            Tier::Fresh => "fresh",
+            Tier::Stale => "stale",
+        }
+    }
+}
+
+/// Whether a lookup found favorites, and how fresh they were.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Lookup {
+    Fresh,
+    Stale,
+    Miss,
+}
+
+impl Lookup {
+    pub fn label(self) -> &'static str {
+        match self {
+            Lookup::Fresh => "fresh",
+            Lookup::Stale => "stale",
+            Lookup::Miss => "miss",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct CacheMetrics {
+    lookups: Family<LookupLabels, Counter>,
+    evictions: Family<EvictionLabels, Counter>,
+    entries: Family<TierLabels, Gauge>,
+    weight: Family<TierLabels, Gauge>,
+}
+
+impl CacheMetrics {
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "cache_lookups",
+            "Lookups of users' favorites by result: fresh, stale, or miss",
+            self.lookups.clone(),
+        );
+        registry.register(
+            "cache_evictions",
+            "Favorites evicted from the cache by tier and cause: expired or size",
+            self.evictions.clone(),
+        );
+        registry.register(
+            "cache_entries",
+            "Users whose favorites are cached, by tier",
+            self.entries.clone(),
+        );
+        registry.register(
+            "cache_weight",
+            "Characters cached, by tier",
+            self.weight.clone(),
+        );
+    }
+
+    pub fn record_lookup(&self, lookup: Lookup) {
+        self.lookups
+            .get_or_create(&LookupLabels {
+                result: lookup.label(),
+            })
+            .inc();
+    }
+
+    /// Count favorites leaving `tier` because they expired or the cache was full.
+    ///
+    /// Favorites replaced by a refresh aren't counted.
+    pub fn record_removal(&self, tier: Tier, cause: RemovalCause) {
+        let cause = match cause {
+            RemovalCause::Expired => "expired",
+            RemovalCause::Size => "size",
+            RemovalCause::Explicit | RemovalCause::Replaced => return,
+        };
+
+        self.evictions
+            .get_or_create(&EvictionLabels {
+                tier: tier.label(),
+                cause,
+            })
+            .inc();
+    }
+
+    pub async fn update_size(&self, tier: Tier, cache: &Cache<String, Favorites>) {
+        cache.run_pending_tasks().await;
+
+        let labels = TierLabels { tier: tier.label() };
+        self.entries
+            .get_or_create(&labels)
+            .set(cache.entry_count().try_into().unwrap_or(i64::MAX));
+        self.weight
+            .get_or_create(&labels)
+            .set(cache.weighted_size().try_into().unwrap_or(i64::MAX));
+    }
+}
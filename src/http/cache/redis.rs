@@ -0,0 +1,108 @@
+//! Favorites kept in Redis, so every replica shares what any of them fetched.
+
+use ::redis::{aio::ConnectionManager, AsyncCommands, Client};
+use anyhow::{Context, Result};
+
+use super::Favorites;
+
+/// Prefixed to usernames to make their keys, so the database can be shared with other data.
+const KEY_PREFIX: &str = "waifu-calendar:favorites:";
+
+/// A connection to a Redis server holding one JSON value of favorites per user.
+#[derive(Clone)]
+pub(super) struct RedisStore {
+    connection: ConnectionManager,
+}
+
+impl RedisStore {
+    /// Connect to the server at `url`, like `redis://127.0.0.1/`.
+    ///
+    /// The connection is re-established automatically if it drops later.
+    pub async fn open(url: &str) -> Result<Self> {
+        let client = Client::open(url).with_context(|| format!("Invalid Redis URL {:?}", url))?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .with_context(|| format!("Failed to connect to Redis at {:?}", url))?;
+
+        Ok(Self { connection })
+    }
+
+    fn key(username: &str) -> String {
+        format!("{}{}", KEY_PREFIX, username)
+    }
+
+    /// Get a user's favorites, if any replica saved them and they haven't expired.
+    pub async fn get(&self, username: &str) -> Result<Option<Favorites>> {
+        let json: Option<String> = self.connection.clone().get(Self::key(username)).await?;
+
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    /// Save a user's favorites, replacing any saved before, for Redis to expire after `ttl`.
+    pub async fn save(
+        &self,
+        username: &str,
+        favorites: &Favorites,
+        ttl: std::time::Duration,
+    ) -> Result<()> {
+        let json = serde_json::to_string(favorites)?;
+
+        // Redis rejects an expiry of zero seconds, and the favorites would be gone anyway.
+        if ttl.as_secs() == 0 {
+            return Ok(());
+        }
+
+        self.connection
+            .clone()
+            .set_ex::<_, _, ()>(Self::key(username), json, ttl.as_secs())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Month, OffsetDateTime};
+
+    use super::RedisStore;
+    use crate::{http::cache::Favorites, Birthday, Character};
+
+    #[test]
+    fn keys_are_prefixed() {
+        assert_eq!(
+            RedisStore::key("Owldown"),
+            "waifu-calendar:favorites:Owldown"
+        );
+    }
+
+    /// Needs a running Redis server, at `WAIFU_TEST_REDIS_URL` or the default port on localhost.
+    #[tokio::test]
+    #[ignore]
+    async fn save_and_get() {
+        let url = std::env::var("WAIFU_TEST_REDIS_URL").unwrap_or("redis://127.0.0.1/".to_string());
+        let store = RedisStore::open(&url).await.unwrap();
+        let username = format!("test-{}", std::process::id());
+
+        let saved = Favorites {
+            characters: vec![Character::new(
+                "Frieren",
+                "https://anilist.co/character/176754",
+                Birthday::new(Month::March, 8),
+            )],
+            fetched_at: OffsetDateTime::now_utc().replace_nanosecond(0).unwrap(),
+            stale: false,
+        };
+
+        store
+            .save(&username, &saved, std::time::Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let loaded = store.get(&username).await.unwrap().unwrap();
+
+        assert_eq!(loaded.characters, saved.characters);
+        assert_eq!(loaded.fetched_at, saved.fetched_at);
+        assert!(store.get("nobody-saved-this").await.unwrap().is_none());
+    }
+}
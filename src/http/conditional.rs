@@ -0,0 +1,248 @@
+//! Validators and freshness headers for conditional requests.
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, IntoResponseParts, Response},
+};
+use time::{
+    format_description::BorrowedFormatItem, macros::format_description, OffsetDateTime,
+    PrimitiveDateTime, UtcOffset,
+};
+
+/// The IMF-fixdate format used in HTTP headers, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+const HTTP_DATE: &[BorrowedFormatItem<'static>] = format_description!(
+    "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+);
+
+/// How long a response stays fresh, and when its data last changed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(super) struct Freshness {
+    /// Sent as `Last-Modified`.
+    pub last_modified: OffsetDateTime,
+    /// Sent as the `max-age` of `Cache-Control`.
+    pub max_age: std::time::Duration,
+    /// If the response was rendered from expired data, how old that data is.
+    ///
+    /// Sent as `Age`, along with a `Warning` that the response is stale.
+    pub stale_age: Option<std::time::Duration>,
+}
+
+/// Respond with `ETag`, `Last-Modified`, and `Cache-Control` headers,
+/// or with `304 Not Modified` if the client's copy is still current.
+pub(super) fn conditional_response(
+    request: &HeaderMap,
+    freshness: &Freshness,
+    parts: impl IntoResponseParts,
+    body: String,
+) -> Response {
+    let etag = etag(body.as_bytes());
+    let last_modified = freshness
+        .last_modified
+        .replace_nanosecond(0)
+        .unwrap_or(freshness.last_modified);
+
+    let mut validators = vec![
+        (header::ETAG, etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", freshness.max_age.as_secs()),
+        ),
+    ];
+
+    if let Some(date) = http_date(&last_modified) {
+        validators.push((header::LAST_MODIFIED, date));
+    }
+
+    if let Some(age) = freshness.stale_age {
+        validators.push((header::AGE, age.as_secs().to_string()));
+        validators.push((header::WARNING, "110 - \"Response is Stale\"".to_string()));
+    }
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in validators {
+        if let Ok(value) = value.parse() {
+            headers.insert(name, value);
+        }
+    }
+
+    if is_not_modified(request, &etag, &last_modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    (headers, parts, body).into_response()
+}
+
+/// Build a strong entity tag from a 64-bit FNV-1a hash of the response body,
+/// so it stays the same across restarts and builds of the server.
+fn etag(body: &[u8]) -> String {
+    hash_etag(fnv1a(body))
+}
+
+/// Build a strong entity tag from a hash computed with [`fnv1a`].
+pub(super) fn hash_etag(hash: u64) -> String {
+    format!("\"{:016x}\"", hash)
+}
+
+/// Hash bytes with 64-bit FNV-1a, at compile time if need be.
+pub(super) const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+
+    hash
+}
+
+/// Check `If-None-Match`, or `If-Modified-Since` when there is no `If-None-Match`.
+fn is_not_modified(request: &HeaderMap, etag: &str, last_modified: &OffsetDateTime) -> bool {
+    if let Some(matched) = if_none_match(request, etag) {
+        return matched;
+    }
+
+    request
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_http_date)
+        .is_some_and(|since| *last_modified <= since)
+}
+
+/// Check whether `If-None-Match` lists `etag`, if the request has one.
+pub(super) fn if_none_match(request: &HeaderMap, etag: &str) -> Option<bool> {
+    let if_none_match = request
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .collect::<Vec<&str>>();
+
+    (!if_none_match.is_empty()).then(|| {
+        if_none_match
+            .iter()
+            .any(|tag| *tag == "*" || tag.trim_start_matches("W/") == etag)
+    })
+}
+
+fn http_date(datetime: &OffsetDateTime) -> Option<String> {
+    datetime.to_offset(UtcOffset::UTC).format(HTTP_DATE).ok()
+}
+
+fn parse_http_date(s: &str) -> Option<OffsetDateTime> {
+    PrimitiveDateTime::parse(s, HTTP_DATE)
+        .ok()
+        .map(|datetime| datetime.assume_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, StatusCode};
+    use time::{macros::datetime, Duration};
+
+    use super::{conditional_response, etag, http_date, parse_http_date, Freshness};
+
+    fn freshness() -> Freshness {
+        Freshness {
+            last_modified: datetime!(1994-11-06 08:49:37 UTC),
+            max_age: std::time::Duration::from_secs(600),
+            stale_age: None,
+        }
+    }
+
+    fn respond(request: &HeaderMap) -> axum::response::Response {
+        conditional_response(
+            request,
+            &freshness(),
+            [(header::CONTENT_TYPE, "text/calendar")],
+            "BEGIN:VCALENDAR".to_string(),
+        )
+    }
+
+    #[test]
+    fn etag_is_fnv1a_of_body() {
+        assert_eq!(etag(b""), "\"cbf29ce484222325\"");
+        assert_eq!(etag(b"a"), "\"af63dc4c8601ec8c\"");
+    }
+
+    #[test]
+    fn http_date_round_trip() {
+        let date = datetime!(1994-11-06 08:49:37 UTC);
+
+        assert_eq!(http_date(&date).unwrap(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+    }
+
+    #[test]
+    fn sends_validators() {
+        let response = respond(&HeaderMap::new());
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::ETAG));
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=600"
+        );
+    }
+
+    #[test]
+    fn warns_when_stale() {
+        let stale = Freshness {
+            stale_age: Some(std::time::Duration::from_secs(1200)),
+            ..freshness()
+        };
+
+        let response = conditional_response(
+            &HeaderMap::new(),
+            &stale,
+            [(header::CONTENT_TYPE, "text/calendar")],
+            "BEGIN:VCALENDAR".to_string(),
+        );
+
+        assert_eq!(response.headers()[header::AGE], "1200");
+        assert_eq!(
+            response.headers()[header::WARNING],
+            "110 - \"Response is Stale\""
+        );
+    }
+
+    #[test]
+    fn not_modified_for_matching_etag() {
+        let etag = respond(&HeaderMap::new()).headers()[header::ETAG].clone();
+
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, etag);
+
+        assert_eq!(respond(&request).status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn modified_for_other_etag() {
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, "\"abc\"".parse().unwrap());
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap(),
+        );
+
+        assert_eq!(respond(&request).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn not_modified_since() {
+        let since = datetime!(1994-11-06 08:49:37 UTC) + Duration::hours(1);
+
+        let mut request = HeaderMap::new();
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            http_date(&since).unwrap().parse().unwrap(),
+        );
+
+        assert_eq!(respond(&request).status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,575 @@
+//! Configuration of the server, read from a TOML file and overridden by
+//! environment variables and command-line flags.
+
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+use anyhow::{bail, ensure, Context, Result};
+use recloser::{AsyncRecloser, Recloser};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use super::{assets::Assets, cache::CacheBackend};
+pub use super::listen::ListenAddr;
+
+/// Everything configurable about the server.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cache: CacheConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub anilist: AniListConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to listen on.
+    pub bind: Vec<ListenAddr>,
+    /// The URL the server is publicly reached at, for links to calendars.
+    /// It may include a path prefix, if the server is proxied beneath one.
+    ///
+    /// If unset, it's worked out from each request's `Host` and `X-Forwarded-Proto` headers.
+    pub public_url: Option<String>,
+    /// The directory holding `templates` and `assets`.
+    ///
+    /// If unset, the templates and assets built into the server are used,
+    /// or those in the working directory if it was built without them.
+    pub assets: Option<PathBuf>,
+    /// Whether to reload templates from disk whenever they change, for working on them.
+    pub dev_mode: bool,
+    /// Seconds to wait for open requests to finish when shutting down.
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![ListenAddr::Tcp(SocketAddr::from(([0, 0, 0, 0], 8080)))],
+            public_url: None,
+            assets: None,
+            dev_mode: false,
+            drain_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Seconds fetched favorites stay in the cache.
+    pub ttl_secs: u64,
+    /// How many characters, across every user, each tier of the cache may hold.
+    pub capacity: u64,
+    /// Seconds expired favorites are kept to serve while they are refreshed.
+    pub stale_grace_period_secs: u64,
+    /// Where favorites are kept besides in memory.
+    pub backend: CacheBackendKind,
+    /// The directory of the disk backend.
+    pub dir: PathBuf,
+    /// The server of the Redis backend.
+    pub redis_url: String,
+    /// How many popular users' favorites may be refreshed from AniList each minute.
+    ///
+    /// AniList allows 90 requests a minute, so the default leaves most of them
+    /// for requests that miss the cache. Zero disables refreshing.
+    pub refresh_budget: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 15 * 60,
+            capacity: 1024 * 1024,
+            stale_grace_period_secs: 24 * 60 * 60,
+            backend: CacheBackendKind::Memory,
+            dir: PathBuf::from("cache"),
+            redis_url: "redis://127.0.0.1/".to_string(),
+            refresh_budget: 30,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+}
+
+impl CacheConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    pub fn stale_grace_period(&self) -> Duration {
+        Duration::from_secs(self.stale_grace_period_secs)
+    }
+
+    pub(super) fn backend(&self) -> CacheBackend {
+        match self.backend {
+            CacheBackendKind::Memory => CacheBackend::Memory,
+            CacheBackendKind::Disk => CacheBackend::Disk(self.dir.clone()),
+            CacheBackendKind::Redis => CacheBackend::Redis(self.redis_url.clone()),
+        }
+    }
+}
+
+/// Where favorites are kept besides in memory.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackendKind {
+    /// Nowhere, so they are lost on restart.
+    Memory,
+    /// A directory of JSON files, loaded back on start.
+    Disk,
+    /// A Redis server, shared by every replica using it.
+    Redis,
+}
+
+/// When to stop calling AniList after it fails repeatedly.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// The share of failed calls, from 0 to 1, that opens the breaker.
+    pub error_rate: f32,
+    /// How many calls the error rate is measured over while closed.
+    pub closed_len: usize,
+    /// How many trial calls are made while half open.
+    pub half_open_len: usize,
+    /// Seconds the breaker stays open before trying AniList again.
+    pub open_wait_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            error_rate: 0.5,
+            closed_len: 100,
+            half_open_len: 10,
+            open_wait_secs: 30,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn open_wait(&self) -> Duration {
+        Duration::from_secs(self.open_wait_secs)
+    }
+
+    pub(super) fn build(&self) -> AsyncRecloser {
+        AsyncRecloser::from(
+            Recloser::custom()
+                .error_rate(self.error_rate)
+                .closed_len(self.closed_len)
+                .half_open_len(self.half_open_len)
+                .open_wait(self.open_wait())
+                .build(),
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AniListConfig {
+    /// The URL of AniList's GraphQL API.
+    pub endpoint: String,
+}
+
+impl Default for AniListConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: crate::ANILIST_ENDPOINT.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Which messages to log, in `tracing-subscriber`'s `EnvFilter` format,
+    /// like `info` or `waifu_calendar=debug`.
+    pub filter: String,
+    pub format: LogFormat,
+    /// The OpenTelemetry collector to export traces to over OTLP/HTTP,
+    /// like `http://localhost:4318/v1/traces`.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            format: LogFormat::Text,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+/// How log lines are written.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lines of text for people to read.
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Overrides of the configuration file, from the command line or the environment.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct ConfigArgs {
+    /// TOML configuration file to read
+    #[arg(short, long, env = "WAIFU_CONFIG", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on, like 0.0.0.0:8080, unix:/path/to/socket, or systemd for
+    /// socket activation. Repeat to listen on several
+    #[arg(long, env = "WAIFU_BIND", value_name = "ADDR", value_delimiter = ',')]
+    pub bind: Vec<ListenAddr>,
+
+    /// URL the server is publicly reached at, like https://waifu-calendar.fly.dev/
+    #[arg(long, env = "WAIFU_PUBLIC_URL", value_name = "URL")]
+    pub public_url: Option<String>,
+
+    /// Directory holding templates and assets, instead of those built into the server
+    #[arg(long, env = "WAIFU_ASSETS", value_name = "DIR")]
+    pub assets: Option<PathBuf>,
+
+    /// Reload templates from disk whenever they change
+    #[arg(long, env = "WAIFU_DEV")]
+    pub dev: bool,
+
+    /// Seconds to wait for open requests to finish when shutting down
+    #[arg(long, env = "WAIFU_DRAIN_TIMEOUT", value_name = "SECS")]
+    pub drain_timeout: Option<u64>,
+
+    /// Seconds fetched favorites stay in the cache
+    #[arg(long, env = "WAIFU_CACHE_TTL", value_name = "SECS")]
+    pub cache_ttl: Option<u64>,
+
+    /// How many characters each tier of the cache may hold
+    #[arg(long, env = "WAIFU_CACHE_CAPACITY", value_name = "CHARACTERS")]
+    pub cache_capacity: Option<u64>,
+
+    /// Seconds expired favorites are served while they are refreshed
+    #[arg(long, env = "WAIFU_STALE_GRACE_PERIOD", value_name = "SECS")]
+    pub stale_grace_period: Option<u64>,
+
+    /// Where favorites are kept besides in memory
+    #[arg(long, env = "WAIFU_CACHE_BACKEND", value_name = "BACKEND")]
+    pub cache_backend: Option<CacheBackendKind>,
+
+    /// Directory of the disk cache backend
+    #[arg(long, env = "WAIFU_CACHE_DIR", value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Server of the Redis cache backend, like redis://127.0.0.1/
+    #[arg(long, env = "WAIFU_REDIS_URL", value_name = "URL")]
+    pub redis_url: Option<String>,
+
+    /// How many popular users may be refreshed from AniList each minute
+    #[arg(long, env = "WAIFU_REFRESH_BUDGET", value_name = "REQUESTS")]
+    pub refresh_budget: Option<usize>,
+
+    /// Share of failed AniList calls, from 0 to 1, that opens the circuit breaker
+    #[arg(long, env = "WAIFU_BREAKER_ERROR_RATE", value_name = "RATE")]
+    pub breaker_error_rate: Option<f32>,
+
+    /// How many AniList calls the error rate is measured over
+    #[arg(long, env = "WAIFU_BREAKER_CLOSED_LEN", value_name = "CALLS")]
+    pub breaker_closed_len: Option<usize>,
+
+    /// How many trial calls are made while the circuit breaker is half open
+    #[arg(long, env = "WAIFU_BREAKER_HALF_OPEN_LEN", value_name = "CALLS")]
+    pub breaker_half_open_len: Option<usize>,
+
+    /// Seconds the circuit breaker stays open before trying AniList again
+    #[arg(long, env = "WAIFU_BREAKER_OPEN_WAIT", value_name = "SECS")]
+    pub breaker_open_wait: Option<u64>,
+
+    /// URL of AniList's GraphQL API
+    #[arg(long, env = "WAIFU_ANILIST_ENDPOINT", value_name = "URL")]
+    pub anilist_endpoint: Option<String>,
+
+    /// Which messages to log, like info or waifu_calendar=debug
+    #[arg(long, env = "RUST_LOG", value_name = "FILTER")]
+    pub log: Option<String>,
+
+    /// How to write log lines
+    #[arg(long, env = "WAIFU_LOG_FORMAT", value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// OpenTelemetry collector to export traces to over OTLP/HTTP,
+    /// like http://localhost:4318/v1/traces
+    #[arg(long, env = "WAIFU_OTLP_ENDPOINT", value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    /// Read the configuration file named by `args`, if any, and apply the rest of `args` over it.
+    pub fn load(args: &ConfigArgs) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let toml = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read config file {:?}", path))?;
+                Self::from_toml(&toml).with_context(|| format!("Invalid config file {:?}", path))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply(args);
+
+        Ok(config)
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    fn apply(&mut self, args: &ConfigArgs) {
+        fn set<T: Clone>(field: &mut T, arg: &Option<T>) {
+            if let Some(value) = arg {
+                *field = value.clone();
+            }
+        }
+
+        if !args.bind.is_empty() {
+            self.server.bind = args.bind.clone();
+        }
+        if args.public_url.is_some() {
+            self.server.public_url = args.public_url.clone();
+        }
+        if args.assets.is_some() {
+            self.server.assets = args.assets.clone();
+        }
+        if args.dev {
+            self.server.dev_mode = true;
+        }
+        set(&mut self.server.drain_timeout_secs, &args.drain_timeout);
+
+        set(&mut self.cache.ttl_secs, &args.cache_ttl);
+        set(&mut self.cache.capacity, &args.cache_capacity);
+        set(
+            &mut self.cache.stale_grace_period_secs,
+            &args.stale_grace_period,
+        );
+        set(&mut self.cache.backend, &args.cache_backend);
+        set(&mut self.cache.dir, &args.cache_dir);
+        set(&mut self.cache.redis_url, &args.redis_url);
+        set(&mut self.cache.refresh_budget, &args.refresh_budget);
+
+        set(
+            &mut self.circuit_breaker.error_rate,
+            &args.breaker_error_rate,
+        );
+        set(
+            &mut self.circuit_breaker.closed_len,
+            &args.breaker_closed_len,
+        );
+        set(
+            &mut self.circuit_breaker.half_open_len,
+            &args.breaker_half_open_len,
+        );
+        set(
+            &mut self.circuit_breaker.open_wait_secs,
+            &args.breaker_open_wait,
+        );
+
+        set(&mut self.anilist.endpoint, &args.anilist_endpoint);
+
+        set(&mut self.log.filter, &args.log);
+        set(&mut self.log.format, &args.log_format);
+        if args.otlp_endpoint.is_some() {
+            self.log.otlp_endpoint = args.otlp_endpoint.clone();
+        }
+    }
+
+    /// Get the public URL of the server, if one is configured.
+    pub(super) fn public_url(&self) -> Result<Option<Url>> {
+        self.server
+            .public_url
+            .as_deref()
+            .map(|url| parse_http_url("server.public_url", url))
+            .transpose()
+    }
+
+    /// Check that the configuration makes sense, beyond each value having the right type.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.server.bind.is_empty(),
+            "server.bind must list at least one address"
+        );
+        ensure!(
+            self.server
+                .bind
+                .iter()
+                .filter(|addr| **addr == ListenAddr::Systemd)
+                .count()
+                <= 1,
+            "server.bind may only list systemd once"
+        );
+        self.public_url()?;
+
+        if let Some(dir) = Assets::new(&self.server).dir() {
+            let templates = dir.join("templates");
+            ensure!(
+                templates.is_dir(),
+                "server.assets must be a directory containing templates, but {:?} is not a directory",
+                templates
+            );
+        }
+
+        ensure!(self.cache.ttl_secs > 0, "cache.ttl_secs must be positive");
+        ensure!(self.cache.capacity > 0, "cache.capacity must be positive");
+        if self.cache.backend == CacheBackendKind::Redis {
+            parse_redis_url(&self.cache.redis_url)?;
+        }
+
+        ensure!(
+            self.circuit_breaker.error_rate > 0.0 && self.circuit_breaker.error_rate <= 1.0,
+            "circuit_breaker.error_rate must be greater than 0 and at most 1"
+        );
+        ensure!(
+            self.circuit_breaker.closed_len > 0,
+            "circuit_breaker.closed_len must be positive"
+        );
+        ensure!(
+            self.circuit_breaker.half_open_len > 0,
+            "circuit_breaker.half_open_len must be positive"
+        );
+
+        parse_http_url("anilist.endpoint", &self.anilist.endpoint)?;
+
+        tracing_subscriber::EnvFilter::try_new(&self.log.filter)
+            .with_context(|| format!("log.filter is not a valid filter: {:?}", self.log.filter))?;
+        if let Some(endpoint) = &self.log.otlp_endpoint {
+            parse_http_url("log.otlp_endpoint", endpoint)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_http_url(name: &str, url: &str) -> Result<Url> {
+    let parsed = Url::parse(url).with_context(|| format!("{} is not a valid URL", name))?;
+
+    if !matches!(parsed.scheme(), "http" | "https") {
+        bail!("{} must be an http or https URL, not {:?}", name, url);
+    }
+
+    Ok(parsed)
+}
+
+fn parse_redis_url(url: &str) -> Result<()> {
+    let parsed = Url::parse(url).context("cache.redis_url is not a valid URL")?;
+
+    if !matches!(parsed.scheme(), "redis" | "rediss" | "unix" | "redis+unix") {
+        bail!("cache.redis_url must be a redis:// URL, not {:?}", url);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheBackendKind, Config, ConfigArgs, ListenAddr};
+
+    #[test]
+    fn defaults_match_previous_behavior() {
+        let config = Config::default();
+
+        assert_eq!(
+            config.server.bind,
+            vec!["0.0.0.0:8080".parse::<ListenAddr>().unwrap()]
+        );
+        assert_eq!(config.cache.ttl_secs, 15 * 60);
+        assert_eq!(config.anilist.endpoint, "https://graphql.anilist.co");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn partial_file_keeps_defaults() {
+        let config = Config::from_toml(
+            r#"
+            [cache]
+            ttl_secs = 60
+            backend = "disk"
+
+            [circuit_breaker]
+            open_wait_secs = 10
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.cache.ttl_secs, 60);
+        assert_eq!(config.cache.backend, CacheBackendKind::Disk);
+        assert_eq!(config.cache.capacity, 1024 * 1024);
+        assert_eq!(config.circuit_breaker.open_wait_secs, 10);
+        assert_eq!(config.circuit_breaker.closed_len, 100);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::from_toml("[cache]\nttl = 60\n").is_err());
+    }
+
+    #[test]
+    fn args_override_file() {
+        let mut config = Config::from_toml("[cache]\nttl_secs = 60\ncapacity = 10\n").unwrap();
+
+        config.apply(&ConfigArgs {
+            cache_ttl: Some(120),
+            bind: vec!["127.0.0.1:3000".parse().unwrap()],
+            ..ConfigArgs::default()
+        });
+
+        assert_eq!(config.cache.ttl_secs, 120);
+        assert_eq!(config.cache.capacity, 10);
+        assert_eq!(
+            config.server.bind,
+            vec!["127.0.0.1:3000".parse::<ListenAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn dump_round_trips() {
+        let mut config = Config::default();
+        config.server.public_url = Some("https://waifu-calendar.fly.dev/".to_string());
+        config
+            .server
+            .bind
+            .push("unix:/run/waifu-calendar.sock".parse().unwrap());
+
+        let dumped = config.to_toml().unwrap();
+
+        assert_eq!(Config::from_toml(&dumped).unwrap(), config);
+    }
+
+    #[test]
+    fn validate_rejects_nonsense() {
+        let mut config = Config::default();
+        config.circuit_breaker.error_rate = 1.5;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.anilist.endpoint = "ftp://anilist.co".to_string();
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.server.bind.clear();
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.server.bind = vec![ListenAddr::Systemd, ListenAddr::Systemd];
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.log.filter = "waifu_calendar=loud".to_string();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Errors shared by every route, rendered in the representation the client asked for.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use tracing::error;
+
+use super::{negotiate::Representation, AppState, NoHandlebarsData};
+use crate::ics::CalendarFormat;
+
+/// Seconds clients should wait before retrying when AniList is limiting or unavailable.
+const RETRY_AFTER_SECS: u64 = 60;
+
+/// An error from any route.
+///
+/// Browsers get an error page, JSON and jCal clients get a JSON error body,
+/// and everyone else gets plain text.
+#[derive(Debug, Serialize)]
+pub(super) struct AppError {
+    #[serde(skip)]
+    status: StatusCode,
+    /// A stable, machine-readable error code.
+    code: &'static str,
+    /// A human-readable description of the error.
+    message: String,
+    /// The template of the error page shown to browsers, if there is one.
+    #[serde(skip)]
+    template: Option<&'static str>,
+    #[serde(skip)]
+    representation: Representation,
+}
+
+/// Marks a response whose body should be replaced with a rendered error page.
+#[derive(Copy, Clone, Debug)]
+struct ErrorPage(&'static str);
+
+impl AppError {
+    fn new(
+        status: StatusCode,
+        code: &'static str,
+        message: impl Into<String>,
+        template: Option<&'static str>,
+    ) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            template,
+            representation: Representation::Html,
+        }
+    }
+
+    pub(super) fn invalid_parameter(name: &str, message: &str) -> Self {
+        Self::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_parameter",
+            format!("Invalid {} parameter: {}", name, message),
+            None,
+        )
+    }
+
+    pub(super) fn bad_request(message: &str) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message, None)
+    }
+
+    pub(super) fn user_not_found(username: &str) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "user_not_found",
+            format!("User {} was not found on AniList", username),
+            Some("user_not_found"),
+        )
+    }
+
+    pub(super) fn rate_limited() -> Self {
+        Self::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Rate limited by AniList, please try again in a minute",
+            Some("too_many_requests"),
+        )
+    }
+
+    pub(super) fn upstream_unavailable() -> Self {
+        Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "upstream_unavailable",
+            "AniList is currently unavailable, please try again in a minute",
+            Some("service_unavailable"),
+        )
+    }
+
+    pub(super) fn internal_error() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Something went wrong",
+            Some("internal_server_error"),
+        )
+    }
+
+    /// Render this error in the given representation instead of as a page.
+    pub(super) fn represented_as(self, representation: Representation) -> Self {
+        Self {
+            representation,
+            ..self
+        }
+    }
+}
+
+impl From<&recloser::Error<anyhow::Error>> for AppError {
+    fn from(e: &recloser::Error<anyhow::Error>) -> Self {
+        match e {
+            recloser::Error::Inner(err) => match err.downcast_ref::<crate::Error>() {
+                Some(crate::Error::UserNotFound(username)) => Self::user_not_found(username),
+                Some(crate::Error::RateLimited) => Self::rate_limited(),
+                Some(other_err) => {
+                    error!("Unknown error fetching from AniList: {:?}", other_err);
+                    Self::internal_error()
+                }
+                None => {
+                    error!("Error contacting AniList: {:?}", err);
+                    Self::internal_error()
+                }
+            },
+            recloser::Error::Rejected => Self::upstream_unavailable(),
+        }
+    }
+}
+
+impl From<Arc<recloser::Error<anyhow::Error>>> for AppError {
+    fn from(e: Arc<recloser::Error<anyhow::Error>>) -> Self {
+        Self::from(e.as_ref())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, template, representation) = (self.status, self.template, self.representation);
+
+        let mut response = match representation {
+            Representation::Json | Representation::Calendar(CalendarFormat::JCal) => {
+                (status, Json(json!({ "error": self }))).into_response()
+            }
+            _ => (
+                status,
+                [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+                format!("{}\n", self.message),
+            )
+                .into_response(),
+        };
+
+        if matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        ) {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+        }
+
+        if let (Representation::Html, Some(template)) = (representation, template) {
+            response.extensions_mut().insert(ErrorPage(template));
+        }
+
+        response
+    }
+}
+
+/// Replace the plain text body of an error shown to a browser with its error page.
+///
+/// If the page fails to render, the plain text body is kept.
+pub(super) async fn render_error_pages(
+    State(state): State<Arc<AppState<'static>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    let Some(ErrorPage(template)) = response.extensions_mut().remove::<ErrorPage>() else {
+        return response;
+    };
+
+    match state.handlebars.render(template, &NoHandlebarsData {}) {
+        Ok(page) => {
+            let (mut parts, _body) = response.into_parts();
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            Response::from_parts(parts, Body::from(page))
+        }
+        Err(err) => {
+            error!("Failed to render error page {}: {:?}", template, err);
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::to_bytes,
+        http::{header, StatusCode},
+        response::IntoResponse,
+    };
+
+    use super::{AppError, ErrorPage};
+    use crate::{http::negotiate::Representation, ics::CalendarFormat};
+
+    #[tokio::test]
+    async fn json_error_body() {
+        let response = AppError::from(&recloser::Error::Rejected)
+            .represented_as(Representation::Json)
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(json["error"]["code"], "upstream_unavailable");
+    }
+
+    #[tokio::test]
+    async fn plain_text_for_calendar_clients() {
+        let response = AppError::rate_limited()
+            .represented_as(Representation::Calendar(CalendarFormat::ICalendar))
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        assert!(response.extensions().get::<ErrorPage>().is_none());
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(
+            body,
+            "Rate limited by AniList, please try again in a minute\n"
+        );
+    }
+
+    #[test]
+    fn error_page_for_browsers() {
+        let err = anyhow::Error::from(crate::Error::UserNotFound("Nobody".to_string()));
+        let response = AppError::from(&recloser::Error::Inner(err)).into_response();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!response.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(
+            response.extensions().get::<ErrorPage>().map(|page| page.0),
+            Some("user_not_found")
+        );
+    }
+
+    #[test]
+    fn network_errors_are_internal() {
+        let err = anyhow::anyhow!("connection refused");
+        let response = AppError::from(&recloser::Error::Inner(err)).into_response();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
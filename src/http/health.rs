@@ -0,0 +1,149 @@
+//! Health and readiness checks for load balancers and orchestrators.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use handlebars::Handlebars;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+
+use super::{upstream::Upstream, AppState};
+
+/// Every template the server renders, which must be loaded for it to be healthy.
+const TEMPLATES: &[&str] = &[
+    "index",
+    "calendar",
+    "user_not_found",
+    "too_many_requests",
+    "service_unavailable",
+    "internal_server_error",
+];
+
+/// Report whether the process is alive and its templates are loaded.
+pub(super) async fn get_healthz(State(state): State<Arc<AppState<'_>>>) -> Response {
+    let (healthy, details) = health(&state.handlebars);
+
+    respond(healthy, details)
+}
+
+/// Report whether the server should get traffic: it's healthy,
+/// and the circuit breaker in front of AniList is closed.
+///
+/// Every instance shares AniList, so they all go unready together while it is down.
+/// Route traffic by `/healthz` instead, and use this to take single instances out.
+pub(super) async fn get_readyz(State(state): State<Arc<AppState<'_>>>) -> Response {
+    let (healthy, _) = health(&state.handlebars);
+    let (ready, details) = readiness(healthy, &state.upstream);
+
+    respond(ready, details)
+}
+
+fn health(handlebars: &Handlebars) -> (bool, Value) {
+    let missing: Vec<_> = TEMPLATES
+        .iter()
+        .filter(|&&name| !handlebars.has_template(name))
+        .collect();
+    let healthy = missing.is_empty();
+
+    (
+        healthy,
+        json!({
+            "status": if healthy { "ok" } else { "unhealthy" },
+            "templates": {
+                "loaded": handlebars.get_templates().len(),
+                "missing": missing,
+            },
+        }),
+    )
+}
+
+fn readiness(healthy: bool, upstream: &Upstream) -> (bool, Value) {
+    let breaker_open = upstream.breaker_open();
+    let ready = healthy && !breaker_open;
+
+    (
+        ready,
+        json!({
+            "status": if ready { "ready" } else { "not_ready" },
+            "healthy": healthy,
+            "circuit_breaker": if breaker_open { "open" } else { "closed" },
+            "last_anilist_success": upstream
+                .last_success()
+                .and_then(|time| time.format(&Rfc3339).ok()),
+        }),
+    )
+}
+
+fn respond(ok: bool, details: Value) -> Response {
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, [(header::CACHE_CONTROL, "no-store")], Json(details)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use handlebars::Handlebars;
+
+    use super::{health, readiness, TEMPLATES};
+    use crate::http::upstream::Upstream;
+
+    #[test]
+    fn unhealthy_without_templates() {
+        let mut handlebars = Handlebars::new();
+        for name in &TEMPLATES[1..] {
+            handlebars.register_template_string(name, "").unwrap();
+        }
+
+        let (healthy, details) = health(&handlebars);
+
+        assert!(!healthy);
+        assert_eq!(details["templates"]["missing"][0], "index");
+
+        handlebars.register_template_string("index", "").unwrap();
+        assert!(health(&handlebars).0);
+    }
+
+    #[test]
+    fn not_ready_while_breaker_is_open() {
+        let upstream = Upstream::new(Duration::from_secs(30));
+        upstream.record_success();
+
+        let (ready, details) = readiness(true, &upstream);
+        assert!(ready);
+        assert_eq!(details["circuit_breaker"], "closed");
+        assert!(details["last_anilist_success"].is_string());
+
+        upstream.record_call(true);
+
+        let (ready, details) = readiness(true, &upstream);
+        assert!(!ready);
+        assert_eq!(details["circuit_breaker"], "open");
+
+        assert!(!readiness(false, &Upstream::new(Duration::from_secs(30))).0);
+    }
+
+    #[test]
+    fn ready_again_once_breaker_has_waited() {
+        let upstream = Upstream::new(Duration::from_millis(20));
+
+        upstream.record_call(true);
+        assert!(!readiness(true, &upstream).0);
+
+        std::thread::sleep(Duration::from_millis(30));
+
+        let (ready, details) = readiness(true, &upstream);
+        assert!(ready);
+        assert_eq!(details["circuit_breaker"], "closed");
+    }
+}
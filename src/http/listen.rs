@@ -0,0 +1,301 @@
+//! Listening on TCP addresses, Unix sockets, and sockets passed by systemd.
+
+use std::{fmt, future::Future, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+/// An address the server listens on.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddr {
+    /// A TCP address, like `0.0.0.0:8080`.
+    Tcp(SocketAddr),
+    /// A Unix domain socket, like `unix:/run/waifu-calendar.sock`.
+    Unix(PathBuf),
+    /// Every socket passed by systemd socket activation, written `systemd`.
+    Systemd,
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "systemd" {
+            return Ok(ListenAddr::Systemd);
+        }
+
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                bail!("expected a socket path after unix:");
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+
+        s.parse().map(ListenAddr::Tcp).map_err(|_| {
+            anyhow!(
+                "expected an address like 0.0.0.0:8080, unix:/path/to/socket, or systemd, not {:?}",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            ListenAddr::Systemd => write!(f, "systemd"),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddr {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ListenAddr> for String {
+    fn from(addr: ListenAddr) -> Self {
+        addr.to_string()
+    }
+}
+
+/// A socket accepting connections.
+pub(super) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "a TCP socket"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                match listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+                {
+                    Some(path) => write!(f, "unix:{}", path),
+                    None => write!(f, "an unnamed Unix socket"),
+                }
+            }
+        }
+    }
+}
+
+impl Listener {
+    /// Serve `router` on this socket until `signal` completes and open connections close.
+    pub async fn serve<F>(self, router: Router, signal: F) -> std::io::Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            Listener::Tcp(listener) => {
+                axum::serve(listener, router)
+                    .with_graceful_shutdown(signal)
+                    .await
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                axum::serve(listener, router)
+                    .with_graceful_shutdown(signal)
+                    .await
+            }
+        }
+    }
+}
+
+/// Start listening on every address.
+pub(super) async fn bind(addrs: &[ListenAddr]) -> Result<Vec<Listener>> {
+    let mut listeners = vec![];
+
+    for addr in addrs {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to listen on {}", addr))?;
+                listeners.push(Listener::Tcp(listener));
+            }
+            ListenAddr::Unix(path) => listeners.push(bind_unix(path)?),
+            ListenAddr::Systemd => listeners.extend(systemd_listeners()?),
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// Listen on a Unix socket, replacing any socket left behind by a previous run.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove old socket {:?}", path))?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on {:?}", path))?;
+
+    Ok(Listener::Unix(listener))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &std::path::Path) -> Result<Listener> {
+    bail!("Unix sockets are only supported on Unix")
+}
+
+/// Take the listening sockets systemd passed to this process.
+///
+/// See `sd_listen_fds(3)`.
+#[cfg(unix)]
+fn systemd_listeners() -> Result<Vec<Listener>> {
+    use std::os::fd::{BorrowedFd, OwnedFd};
+
+    /// The first file descriptor systemd passes sockets in.
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let pid = std::env::var("LISTEN_PID")
+        .context("Not started by systemd socket activation, LISTEN_PID is not set")?;
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        bail!("The sockets systemd passed are for another process");
+    }
+
+    let fds: i32 = std::env::var("LISTEN_FDS")
+        .context("Not started by systemd socket activation, LISTEN_FDS is not set")?
+        .parse()
+        .context("LISTEN_FDS must be a number of sockets")?;
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds)
+        .map(|fd| {
+            // SAFETY: F_GETFD only reads the descriptor's flags, and fails on a closed one.
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("systemd did not pass a socket in fd {}", fd));
+            }
+
+            // SAFETY: fd is open, as checked above, and nothing in this process closes
+            // the fds systemd passed. They are duplicated rather than owned, so a wrong
+            // LISTEN_FDS is an error instead of closing some other file.
+            let owned = unsafe { BorrowedFd::borrow_raw(fd) }
+                .try_clone_to_owned()
+                .with_context(|| format!("Failed to duplicate fd {}", fd))?;
+            let tcp = std::net::TcpListener::from(owned);
+
+            if tcp.local_addr().is_ok() {
+                tcp.set_nonblocking(true)?;
+                return Ok(Listener::Tcp(TcpListener::from_std(tcp)?));
+            }
+
+            let unix = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp));
+            if unix.local_addr().is_err() {
+                bail!("systemd passed fd {}, which is not a TCP or Unix socket", fd);
+            }
+            unix.set_nonblocking(true)?;
+
+            Ok(Listener::Unix(tokio::net::UnixListener::from_std(unix)?))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn systemd_listeners() -> Result<Vec<Listener>> {
+    bail!("systemd socket activation is only supported on Unix")
+}
+
+/// Wait for SIGINT, or SIGTERM on Unix.
+pub(super) async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {:?}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {:?}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => {}
+        () = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{bind, ListenAddr};
+
+    #[test]
+    fn parse_listen_addrs() {
+        assert_eq!(
+            "0.0.0.0:8080".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp("0.0.0.0:8080".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:/run/waifu-calendar.sock"
+                .parse::<ListenAddr>()
+                .unwrap(),
+            ListenAddr::Unix(PathBuf::from("/run/waifu-calendar.sock"))
+        );
+        assert_eq!(
+            "systemd".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Systemd
+        );
+        assert!("localhost".parse::<ListenAddr>().is_err());
+        assert!("unix:".parse::<ListenAddr>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for addr in ["[::1]:8080", "unix:/run/waifu-calendar.sock", "systemd"] {
+            assert_eq!(addr.parse::<ListenAddr>().unwrap().to_string(), addr);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn bind_replaces_old_unix_socket() {
+        let path = std::env::temp_dir().join(format!("waifu-calendar-{}.sock", std::process::id()));
+        let addrs = [ListenAddr::Unix(path.clone())];
+
+        let first = bind(&addrs).await.unwrap();
+        drop(first);
+
+        let second = bind(&addrs).await.unwrap();
+        assert_eq!(second.len(), 1);
+        assert!(tokio::net::UnixStream::connect(&path).await.is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
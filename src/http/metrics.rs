@@ -0,0 +1,288 @@
+//! Prometheus metrics about requests, the cache, and AniList.
+
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use tracing::error;
+
+use super::{cache::FavoritesCache, AppState};
+use crate::RequestOutcome;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, EncodeLabelSet)]
+struct RequestLabels {
+    method: String,
+    /// The route's path pattern, like `/u/{username}`, so usernames don't become labels.
+    route: String,
+    status: u16,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, EncodeLabelSet)]
+struct OutcomeLabels {
+    outcome: &'static str,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, EncodeLabelSet)]
+struct BreakerLabels {
+    state: &'static str,
+}
+
+/// Every metric the server exports.
+pub(super) struct Metrics {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: Family<RequestLabels, Histogram>,
+    anilist_requests: Family<OutcomeLabels, Counter>,
+    anilist_pages: Histogram,
+    anilist_fetches_in_flight: Gauge,
+    breaker_open: Gauge,
+    breaker_transitions: Family<BreakerLabels, Counter>,
+}
+
+/// Counts an AniList fetch as in flight until dropped.
+pub(super) struct InFlight(Gauge);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl Metrics {
+    /// Build the metrics, including those `cache` keeps about itself.
+    pub fn new(cache: &FavoritesCache) -> Self {
+        let mut registry = Registry::with_prefix("waifu");
+
+        let requests = Family::<RequestLabels, Counter>::default();
+        registry.register(
+            "http_requests",
+            "HTTP requests by route and status",
+            requests.clone(),
+        );
+
+        let request_duration = Family::<RequestLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(exponential_buckets(0.005, 2.0, 12))
+        });
+        registry.register(
+            "http_request_duration_seconds",
+            "Time taken to respond to HTTP requests by route and status",
+            request_duration.clone(),
+        );
+
+        cache.register_metrics(&mut registry);
+
+        let anilist_requests = Family::<OutcomeLabels, Counter>::default();
+        registry.register(
+            "anilist_requests",
+            "Requests to AniList by outcome: ok, not_found, rate_limited, or error",
+            anilist_requests.clone(),
+        );
+
+        let anilist_pages = Histogram::new([1.0, 2.0, 3.0, 5.0, 10.0, 20.0]);
+        registry.register(
+            "anilist_pages_per_fetch",
+            "Pages of favorites requested from AniList to fetch one user",
+            anilist_pages.clone(),
+        );
+
+        // Requests to AniList aren't queued behind a rate limiter, which would have
+        // a queue depth to report, so report how many fetches are waiting on AniList.
+        let anilist_fetches_in_flight = Gauge::default();
+        registry.register(
+            "anilist_fetches_in_flight",
+            "Users whose favorites are being fetched from AniList, which isn't rate limited locally, so nothing queues",
+            anilist_fetches_in_flight.clone(),
+        );
+
+        let breaker_open = Gauge::default();
+        registry.register(
+            "circuit_breaker_open",
+            "Whether the circuit breaker in front of AniList is open",
+            breaker_open.clone(),
+        );
+
+        let breaker_transitions = Family::<BreakerLabels, Counter>::default();
+        registry.register(
+            "circuit_breaker_transitions",
+            "Times the circuit breaker in front of AniList opened or closed",
+            breaker_transitions.clone(),
+        );
+
+        Self {
+            registry,
+            requests,
+            request_duration,
+            anilist_requests,
+            anilist_pages,
+            anilist_fetches_in_flight,
+            breaker_open,
+            breaker_transitions,
+        }
+    }
+
+    /// Count a request to AniList.
+    pub fn record_anilist_request(&self, outcome: RequestOutcome) {
+        let outcome = match outcome {
+            RequestOutcome::Ok => "ok",
+            RequestOutcome::NotFound => "not_found",
+            RequestOutcome::RateLimited => "rate_limited",
+            RequestOutcome::Error => "error",
+        };
+
+        self.anilist_requests
+            .get_or_create(&OutcomeLabels { outcome })
+            .inc();
+    }
+
+    /// Count a fetch of a user's favorites from AniList, until the returned guard is dropped.
+    pub fn start_anilist_fetch(&self) -> InFlight {
+        self.anilist_fetches_in_flight.inc();
+        InFlight(self.anilist_fetches_in_flight.clone())
+    }
+
+    /// Record how many pages a successful fetch of a user's favorites took.
+    pub fn record_anilist_pages(&self, pages: u32) {
+        self.anilist_pages.observe(pages.into());
+    }
+
+    /// Record the circuit breaker opening or closing.
+    pub fn record_breaker_transition(&self, open: bool) {
+        self.breaker_open.set(open.into());
+        self.breaker_transitions
+            .get_or_create(&BreakerLabels {
+                state: if open { "open" } else { "closed" },
+            })
+            .inc();
+    }
+
+    fn encode(&self) -> Result<String, std::fmt::Error> {
+        let mut body = String::new();
+        encode(&mut body, &self.registry)?;
+        Ok(body)
+    }
+}
+
+/// Count each request and time the response, by route and status.
+pub(super) async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or("unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    let labels = RequestLabels {
+        method,
+        route,
+        status: response.status().as_u16(),
+    };
+    metrics.requests.get_or_create(&labels).inc();
+    metrics
+        .request_duration
+        .get_or_create(&labels)
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Serve every metric in the OpenMetrics text format.
+pub(super) async fn get_metrics(State(state): State<Arc<AppState<'_>>>) -> Response {
+    state.cache.update_size_metrics().await;
+
+    match state.metrics.encode() {
+        Ok(body) => (
+            [(
+                header::CONTENT_TYPE,
+                "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            )],
+            body,
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Failed to encode metrics: {:?}", err);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{middleware, routing::get, Router};
+
+    use super::{track_requests, Metrics};
+    use crate::{
+        http::{cache::FavoritesCache, config::CacheConfig},
+        RequestOutcome,
+    };
+
+    fn metrics() -> Metrics {
+        Metrics::new(&FavoritesCache::new(&CacheConfig::default()))
+    }
+
+    #[test]
+    fn encodes_anilist_metrics() {
+        let metrics = metrics();
+
+        metrics.record_anilist_request(RequestOutcome::RateLimited);
+        metrics.record_anilist_pages(2);
+        metrics.record_breaker_transition(true);
+        drop(metrics.start_anilist_fetch());
+
+        let body = metrics.encode().unwrap();
+
+        assert!(body.contains(r#"waifu_anilist_requests_total{outcome="rate_limited"} 1"#));
+        assert!(body.contains("waifu_anilist_pages_per_fetch_count 1"));
+        assert!(body.contains("waifu_anilist_fetches_in_flight 0"));
+        assert!(body.contains("waifu_circuit_breaker_open 1"));
+        assert!(body.contains(r#"waifu_circuit_breaker_transitions_total{state="open"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn requests_are_labeled_by_route() {
+        let metrics = Arc::new(metrics());
+        let router = Router::new()
+            .route("/u/{username}", get(|| async { "Owldown" }))
+            .layer(middleware::from_fn_with_state(
+                metrics.clone(),
+                track_requests,
+            ));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        reqwest::get(format!("http://{}/u/Owldown", addr))
+            .await
+            .unwrap();
+
+        let body = metrics.encode().unwrap();
+
+        assert!(body.contains(
+            r#"waifu_http_requests_total{method="GET",route="/u/{username}",status="200"} 1"#
+        ));
+        assert!(!body.contains("Owldown"));
+    }
+}
@@ -0,0 +1,146 @@
+//! Choosing a representation of a user's birthdays from the `Accept` header.
+
+use axum::http::{header, HeaderMap};
+
+use crate::ics::CalendarFormat;
+
+/// A way of presenting a user's birthdays over HTTP.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(super) enum Representation {
+    /// The calendar web page.
+    Html,
+    /// A calendar file in the given format.
+    Calendar(CalendarFormat),
+    /// The JSON body of the birthdays API.
+    Json,
+    /// vCard contacts.
+    VCard,
+}
+
+impl Representation {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "text/html" | "application/xhtml+xml" => Some(Representation::Html),
+            "application/json" => Some(Representation::Json),
+            "text/vcard" | "text/x-vcard" => Some(Representation::VCard),
+            other => CalendarFormat::from_content_type(other).map(Representation::Calendar),
+        }
+    }
+}
+
+/// Pick the representation the client prefers most, according to `Accept`.
+///
+/// Clients that send no `Accept` header, or accept anything, get `default`.
+/// Clients that accept any text type get an iCalendar file, since birthdays are a calendar
+/// first, and `text/html` is only preferred when asked for by name.
+/// Returns `None` if the client accepts none of the representations.
+pub(super) fn negotiate(headers: &HeaderMap, default: Representation) -> Option<Representation> {
+    let mut media_ranges = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_media_range)
+        .collect::<Vec<(String, f32)>>();
+
+    if media_ranges.is_empty() {
+        return Some(default);
+    }
+
+    // A stable sort keeps the client's order among ranges of equal quality.
+    media_ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    media_ranges
+        .iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .find_map(|(media_type, _)| match media_type.as_str() {
+            "*/*" => Some(default),
+            "text/*" => Some(Representation::Calendar(CalendarFormat::ICalendar)),
+            other => Representation::from_media_type(other),
+        })
+}
+
+/// Split a media range like `text/calendar;q=0.8` into its lowercase type and quality.
+fn parse_media_range(media_range: &str) -> Option<(String, f32)> {
+    let mut params = media_range.split(';');
+    let media_type = params.next()?.trim().to_ascii_lowercase();
+
+    if media_type.is_empty() {
+        return None;
+    }
+
+    let quality = params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .and_then(|(_, value)| value.trim().parse::<f32>().ok())
+        .unwrap_or(1.0);
+
+    Some((media_type, quality))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap};
+
+    use super::{negotiate, Representation};
+    use crate::ics::CalendarFormat;
+
+    fn accepting(accept: &str) -> Option<Representation> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, accept.parse().unwrap());
+        negotiate(&headers, Representation::Html)
+    }
+
+    #[test]
+    fn negotiate_without_accept() {
+        assert_eq!(
+            negotiate(&HeaderMap::new(), Representation::VCard),
+            Some(Representation::VCard)
+        );
+    }
+
+    #[test]
+    fn negotiate_browser() {
+        assert_eq!(
+            accepting("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            Some(Representation::Html)
+        );
+    }
+
+    #[test]
+    fn negotiate_by_quality() {
+        assert_eq!(
+            accepting("application/json;q=0.5, text/calendar"),
+            Some(Representation::Calendar(CalendarFormat::ICalendar))
+        );
+        assert_eq!(
+            accepting("application/calendar+json, text/html;q=0.1"),
+            Some(Representation::Calendar(CalendarFormat::JCal))
+        );
+    }
+
+    #[test]
+    fn negotiate_any_text_as_calendar() {
+        assert_eq!(
+            accepting("text/*"),
+            Some(Representation::Calendar(CalendarFormat::ICalendar))
+        );
+        assert_eq!(
+            accepting("text/html;q=0.5, text/*"),
+            Some(Representation::Calendar(CalendarFormat::ICalendar))
+        );
+        assert_eq!(accepting("text/html, text/*"), Some(Representation::Html));
+    }
+
+    #[test]
+    fn negotiate_json_and_vcard() {
+        assert_eq!(accepting("application/json"), Some(Representation::Json));
+        assert_eq!(accepting("text/vcard"), Some(Representation::VCard));
+    }
+
+    #[test]
+    fn negotiate_nothing_acceptable() {
+        assert_eq!(accepting("image/png"), None);
+        assert_eq!(accepting("text/calendar;q=0"), None);
+    }
+}
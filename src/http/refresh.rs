@@ -0,0 +1,152 @@
+//! Refreshing popular users' favorites in the background, before they expire.
+//!
+//! Calendar apps poll on a schedule, so without this the same users would be fetched
+//! from AniList by whichever request finds their favorites expired.
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use tokio::task::AbortHandle;
+use tracing::{debug, warn};
+
+use super::{cache::FavoritesCache, fetch_from_anilist, AppState};
+use crate::Character;
+
+/// How often to look for favorites to refresh.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long before they expire favorites are refreshed.
+///
+/// Longer than [`REFRESH_INTERVAL`], so favorites don't expire between two sweeps.
+const REFRESH_AHEAD: Duration = Duration::from_secs(2 * 60);
+
+/// How many requests make a user popular enough to refresh.
+const POPULAR_HITS: u64 = 2;
+
+/// Refresh popular users' favorites every [`REFRESH_INTERVAL`],
+/// fetching at most `budget` users from AniList each time.
+///
+/// A budget of zero disables refreshing. Otherwise, returns a handle to stop refreshing with.
+pub(super) fn spawn(state: Arc<AppState<'static>>, budget: usize) -> Option<AbortHandle> {
+    if budget == 0 {
+        return None;
+    }
+
+    let refresher = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let refreshed = sweep(&state.cache, budget, |username| {
+                fetch_from_anilist(&state, username)
+            })
+            .await;
+
+            if refreshed > 0 {
+                debug!("Refreshed favorites of {} popular users", refreshed);
+            }
+        }
+    });
+
+    Some(refresher.abort_handle())
+}
+
+/// Refresh the favorites of up to `budget` popular users that are about to expire,
+/// most popular first, returning how many were refreshed.
+///
+/// Stops early if AniList is rate limiting or unavailable, leaving the rest to expire.
+async fn sweep<F, Fut>(cache: &FavoritesCache, budget: usize, fetch: F) -> usize
+where
+    F: Fn(&str) -> Fut,
+    Fut: Future<Output = Result<Vec<Character>, recloser::Error<anyhow::Error>>>,
+{
+    let mut refreshed = 0;
+
+    for username in cache.popular_usernames(POPULAR_HITS) {
+        if refreshed >= budget {
+            break;
+        }
+
+        if !cache.expires_within(&username, REFRESH_AHEAD).await {
+            continue;
+        }
+
+        refreshed += 1;
+
+        if let Err(err) = cache.refresh(&username, fetch(&username)).await {
+            warn!("Failed to refresh favorites for {}: {:?}", username, err);
+
+            let unavailable = match err.as_ref() {
+                recloser::Error::Rejected => true,
+                recloser::Error::Inner(err) => {
+                    matches!(err.downcast_ref(), Some(crate::Error::RateLimited))
+                }
+            };
+
+            if unavailable {
+                break;
+            }
+        }
+    }
+
+    refreshed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::sweep;
+    use crate::http::{cache::FavoritesCache, config::CacheConfig};
+
+    async fn popular_cache(usernames: &[&str]) -> FavoritesCache {
+        let cache = FavoritesCache::new(&CacheConfig::default());
+
+        for username in usernames {
+            for _ in 0..2 {
+                cache
+                    .get_or_fetch(username, async { Ok(vec![]) })
+                    .await
+                    .unwrap();
+            }
+
+            cache.expire(username).await;
+        }
+
+        cache
+    }
+
+    #[tokio::test]
+    async fn sweep_stays_within_budget() {
+        let cache = popular_cache(&["Owldown", "Frieren", "Fern"]).await;
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let refreshed = sweep(&cache, 2, |_| {
+            let fetches = fetches.clone();
+            async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![])
+            }
+        })
+        .await;
+
+        assert_eq!(refreshed, 2);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // The third user is refreshed next time, and the others are fresh until then.
+        assert_eq!(sweep(&cache, 2, |_| async { Ok(vec![]) }).await, 1);
+    }
+
+    #[tokio::test]
+    async fn sweep_stops_when_anilist_is_unavailable() {
+        let cache = popular_cache(&["Owldown", "Frieren"]).await;
+
+        let refreshed = sweep(&cache, 10, |_| async { Err(recloser::Error::Rejected) }).await;
+
+        assert_eq!(refreshed, 1);
+    }
+}
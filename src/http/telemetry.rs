@@ -0,0 +1,188 @@
+//! Logging, and tracing requests through the server to AniList.
+//!
+//! Each request runs in a `request` span carrying its ID, the username it's for,
+//! whether the cache had their favorites, and how many pages were fetched from AniList.
+
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing::{field, info, info_span, Instrument};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use uuid::Uuid;
+
+use super::config::{LogConfig, LogFormat};
+
+/// The header clients and proxies identify requests with, echoed back in responses.
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// The longest request ID accepted from clients, so they can't bloat every log line.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The service name traces are exported under.
+const SERVICE_NAME: &str = "waifu-calendar";
+
+/// Keeps exporting traces until dropped, then exports any that are left.
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Failed to export remaining traces: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Start logging as configured, and exporting traces if a collector is configured.
+///
+/// Messages logged through the `log` crate are logged too.
+pub fn init(config: &LogConfig) -> Result<Telemetry> {
+    let filter = EnvFilter::try_new(&config.filter)
+        .with_context(|| format!("Invalid log filter {:?}", config.filter))?;
+
+    let output = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let tracer_provider = config
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+                .context("Failed to build OpenTelemetry exporter")?;
+
+            anyhow::Ok(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+                    .build(),
+            )
+        })
+        .transpose()?;
+
+    let traces = tracer_provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(traces)
+        .try_init()
+        .context("Failed to start logging")?;
+
+    Ok(Telemetry { tracer_provider })
+}
+
+/// Run each request in a span identified by the client's `X-Request-Id`, or a new one,
+/// and log how it went.
+pub(super) async fn trace_requests(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or("unmatched".to_string());
+
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        route,
+        username = field::Empty,
+        cache = field::Empty,
+        pages = field::Empty,
+        status = field::Empty,
+    );
+
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    let status = response.status().as_u16();
+    span.record("status", status);
+    span.in_scope(|| {
+        info!(
+            latency_ms = start.elapsed().as_millis() as u64,
+            "Responded {}", status
+        )
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+
+    response
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+
+    use super::{is_valid_request_id, trace_requests};
+
+    #[test]
+    fn request_ids_must_be_short_and_printable() {
+        assert!(is_valid_request_id("f3a1c2e4-5b6d-4e7f-8a9b-0c1d2e3f4a5b"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("two words"));
+        assert!(!is_valid_request_id(&"a".repeat(200)));
+    }
+
+    #[tokio::test]
+    async fn responses_carry_request_id() {
+        let router = Router::new()
+            .route("/", get(|| async { "Frieren" }))
+            .layer(middleware::from_fn(trace_requests));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/", addr);
+
+        let response = client
+            .get(&url)
+            .header("X-Request-Id", "abc-123")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+
+        let response = client.get(&url).send().await.unwrap();
+        let generated = response.headers()["x-request-id"].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok());
+    }
+}
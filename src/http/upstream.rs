@@ -0,0 +1,114 @@
+//! What calls to AniList have shown about its state.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use time::OffsetDateTime;
+
+/// The state of AniList and the circuit breaker in front of it, as seen by calls through it.
+///
+/// The breaker doesn't expose its state, so it's taken to be open once it rejects a call,
+/// until it lets one through or has waited long enough to try AniList again.
+#[derive(Debug)]
+pub(super) struct Upstream {
+    /// How long the breaker stays open before trying AniList again.
+    open_wait: Duration,
+    last_rejected: Mutex<Option<Instant>>,
+    last_success: Mutex<Option<OffsetDateTime>>,
+}
+
+impl Upstream {
+    pub fn new(open_wait: Duration) -> Self {
+        Self {
+            open_wait,
+            last_rejected: Mutex::default(),
+            last_success: Mutex::default(),
+        }
+    }
+
+    /// Record whether the breaker rejected a call.
+    ///
+    /// Returns whether the breaker is now open, if that changed.
+    pub fn record_call(&self, rejected: bool) -> Option<bool> {
+        let mut last_rejected = self
+            .last_rejected
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        let was_open = self.is_open(*last_rejected);
+        *last_rejected = rejected.then(Instant::now);
+
+        (was_open != rejected).then_some(rejected)
+    }
+
+    /// Record a call that fetched a user's favorites from AniList.
+    pub fn record_success(&self) {
+        *self
+            .last_success
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Some(OffsetDateTime::now_utc());
+    }
+
+    pub fn breaker_open(&self) -> bool {
+        self.is_open(
+            *self
+                .last_rejected
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()),
+        )
+    }
+
+    fn is_open(&self, last_rejected: Option<Instant>) -> bool {
+        last_rejected.is_some_and(|rejected| rejected.elapsed() < self.open_wait)
+    }
+
+    /// When favorites were last fetched from AniList, if ever since starting.
+    pub fn last_success(&self) -> Option<OffsetDateTime> {
+        *self
+            .last_success
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Upstream;
+
+    #[test]
+    fn record_call_reports_transitions() {
+        let upstream = Upstream::new(Duration::from_secs(30));
+
+        assert_eq!(upstream.record_call(false), None);
+        assert_eq!(upstream.record_call(true), Some(true));
+        assert_eq!(upstream.record_call(true), None);
+        assert!(upstream.breaker_open());
+        assert_eq!(upstream.record_call(false), Some(false));
+        assert!(!upstream.breaker_open());
+    }
+
+    #[test]
+    fn breaker_closes_after_open_wait() {
+        let upstream = Upstream::new(Duration::from_millis(20));
+
+        upstream.record_call(true);
+        assert!(upstream.breaker_open());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!upstream.breaker_open());
+        assert_eq!(upstream.record_call(true), Some(true));
+    }
+
+    #[test]
+    fn record_success_remembers_last_success() {
+        let upstream = Upstream::new(Duration::from_secs(30));
+        assert_eq!(upstream.last_success(), None);
+
+        upstream.record_success();
+        assert!(upstream.last_success().is_some());
+    }
+}
@@ -1,41 +1,448 @@
 //! Tools for making ICalendar data.
 
-use crate::Character;
+pub mod jcal;
+pub mod xcal;
+
+use std::str::FromStr;
+
+use crate::{Birthday, Character};
 use ics::{
-    parameters,
-    properties::{DtStart, Summary},
-    Event, ICalendar,
+    components::Property,
+    escape_text, parameters,
+    properties::{Description, DtEnd, DtStart, Summary, TzName, URL},
+    Daylight, Event, ICalendar, Standard, TimeZone,
 };
+use tz::{LocalTimeType, TimeZoneRef};
 use uuid::Uuid;
 
-use anyhow::Result;
-use time::{Date, Duration, OffsetDateTime};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use time::{macros::format_description, Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+/// How each birthday is laid out on the calendar.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum EventStyle {
+    /// An all-day `VALUE=DATE` event.
+    #[default]
+    AllDay,
+    /// An event starting at a local time in the calendar's time zone.
+    Timed {
+        /// Local time of day the event starts at.
+        start: Time,
+        /// How long the event lasts.
+        duration: Duration,
+    },
+}
+
+impl EventStyle {
+    /// A timed event covering the whole day, from 00:00 to 23:59.
+    pub fn full_day() -> Self {
+        EventStyle::Timed {
+            start: Time::MIDNIGHT,
+            duration: Duration::hours(23) + Duration::minutes(59),
+        }
+    }
+
+    /// A one-hour timed event starting at the given local time.
+    pub fn at(start: Time) -> Self {
+        EventStyle::Timed {
+            start,
+            duration: Duration::hours(1),
+        }
+    }
+}
+
+impl FromStr for EventStyle {
+    type Err = anyhow::Error;
+
+    /// Parses `all-day`, `timed` (a full-day timed event), or an `HH:MM` start time.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all-day" => Ok(EventStyle::AllDay),
+            "timed" => Ok(EventStyle::full_day()),
+            _ => {
+                let (hour, minute) = s
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Unknown event style {:?}", s))?;
+                let hour: u8 = hour.parse().with_context(|| format!("Invalid hour in {:?}", s))?;
+                let minute: u8 =
+                    minute.parse().with_context(|| format!("Invalid minute in {:?}", s))?;
+                let start = Time::from_hms(hour, minute, 0)
+                    .with_context(|| format!("Invalid time of day {:?}", s))?;
+
+                Ok(EventStyle::at(start))
+            }
+        }
+    }
+}
+
+/// The most years a `Horizon` may span.
+pub const MAX_HORIZON_YEARS: i32 = 100;
+
+/// A span of time to expand birthdays into individual events over.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Horizon {
+    /// The given number of years, starting today.
+    Years(u32),
+    /// Every date from `from` to `to`, inclusive.
+    Range { from: Date, to: Date },
+}
+
+impl Horizon {
+    /// Build a horizon from `years`, `from` and `to` parameters, if any were given.
+    ///
+    /// `from` defaults to `today`, and `to` defaults to one year after `from`.
+    /// `years` can't be combined with a date range.
+    pub fn from_params(
+        today: &Date,
+        years: Option<u32>,
+        from: Option<Date>,
+        to: Option<Date>,
+    ) -> Result<Option<Self>> {
+        let horizon = match (years, from, to) {
+            (None, None, None) => return Ok(None),
+            (Some(years), None, None) => {
+                ensure!(
+                    i64::from(years) <= i64::from(MAX_HORIZON_YEARS),
+                    "A horizon can't span more than {} years",
+                    MAX_HORIZON_YEARS
+                );
+                Horizon::Years(years)
+            }
+            (Some(_), _, _) => bail!("A horizon can't have both years and a date range"),
+            (None, from, to) => {
+                let from = from.unwrap_or(*today);
+                let to = match to {
+                    Some(to) => to,
+                    None => last_day_within_years(&from, 1)?,
+                };
+                Horizon::Range { from, to }
+            }
+        };
+
+        let (from, to) = horizon.date_range(today)?;
+
+        ensure!(from <= to, "A horizon must start before it ends");
+        ensure!(
+            to < add_years(&from, MAX_HORIZON_YEARS)?,
+            "A horizon can't span more than {} years",
+            MAX_HORIZON_YEARS
+        );
+
+        Ok(Some(horizon))
+    }
+
+    /// Get the first and last dates covered by this horizon, inclusive.
+    pub fn date_range(&self, today: &Date) -> Result<(Date, Date)> {
+        match self {
+            Horizon::Years(years) => {
+                let years: i32 = (*years).try_into().context("Too many years in horizon")?;
+                Ok((*today, last_day_within_years(today, years)?))
+            }
+            Horizon::Range { from, to } => Ok((*from, *to)),
+        }
+    }
+}
+
+/// Parse an ISO 8601 calendar date, like `2024-01-13`.
+pub fn parse_date(s: &str) -> Result<Date> {
+    Date::parse(s, format_description!("[year]-[month]-[day]"))
+        .with_context(|| format!("Invalid date {:?}, expected YYYY-MM-DD", s))
+}
+
+fn add_years(date: &Date, years: i32) -> Result<Date> {
+    let year = date
+        .year()
+        .checked_add(years)
+        .with_context(|| format!("Failed to add {} years to {}", years, date))?;
+
+    // February 29th falls back to February 28th in non-leap years.
+    Date::from_calendar_date(year, date.month(), date.day())
+        .or_else(|_| Date::from_calendar_date(year, date.month(), date.day() - 1))
+        .with_context(|| format!("Failed to add {} years to {}", years, date))
+}
+
+/// Get the last date before the same calendar day `years` years after `date`.
+///
+/// From February 29th into a non-leap year, that's February 28th, as there's no same day.
+fn last_day_within_years(date: &Date, years: i32) -> Result<Date> {
+    let end = add_years(date, years)?;
+
+    if end.day() == date.day() {
+        Ok(end - Duration::days(1))
+    } else {
+        Ok(end)
+    }
+}
+
+/// Calendar-level properties describing a birthday calendar.
+///
+/// Subscribing clients use these to name, color, and refresh the calendar.
+/// Every field is optional, and unset fields are left out of the output.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct CalendarOptions {
+    /// Display name of the calendar, emitted as `NAME` and `X-WR-CALNAME`.
+    pub name: Option<String>,
+    /// Longer description of the calendar, emitted as `DESCRIPTION` and `X-WR-CALDESC`.
+    pub description: Option<String>,
+    /// IANA time zone name of the calendar, emitted as `X-WR-TIMEZONE`.
+    ///
+    /// Timed events are placed in this zone, with a matching `VTIMEZONE`.
+    pub timezone: Option<String>,
+    /// How often clients should poll for updates,
+    /// emitted as `REFRESH-INTERVAL` and `X-PUBLISHED-TTL`.
+    pub refresh_interval: Option<Duration>,
+    /// A CSS3 color name, emitted as `COLOR`.
+    pub color: Option<String>,
+    /// How each birthday is laid out on the calendar.
+    pub event_style: EventStyle,
+    /// Emit one event per occurrence within this horizon,
+    /// instead of only each character's next birthday.
+    pub horizon: Option<Horizon>,
+    /// When the characters were last fetched, emitted as each event's `DTSTAMP`.
+    ///
+    /// Defaults to the current time. Setting it makes the output the same
+    /// every time it's rendered from the same data on the same day.
+    pub last_modified: Option<OffsetDateTime>,
+}
+
+impl CalendarOptions {
+    /// Build options with a name and description for an AniList user's calendar.
+    pub fn for_user(username: &str) -> Self {
+        Self {
+            name: Some(format!("{}'s waifu birthdays", username)),
+            description: Some(format!(
+                "Birthdays of {}'s favorite characters on AniList",
+                username
+            )),
+            ..Default::default()
+        }
+    }
+}
+
+/// A way of writing out a calendar.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum CalendarFormat {
+    /// ICalendar text ([RFC 5545](https://www.rfc-editor.org/rfc/rfc5545)).
+    #[default]
+    ICalendar,
+    /// ICalendar as JSON ([RFC 7265](https://www.rfc-editor.org/rfc/rfc7265)).
+    JCal,
+    /// ICalendar as XML ([RFC 6321](https://www.rfc-editor.org/rfc/rfc6321)).
+    XCal,
+}
+
+impl CalendarFormat {
+    /// Get the MIME type of this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CalendarFormat::ICalendar => "text/calendar",
+            CalendarFormat::JCal => "application/calendar+json",
+            CalendarFormat::XCal => "application/calendar+xml",
+        }
+    }
+
+    /// Get the usual file extension of this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CalendarFormat::ICalendar => "ics",
+            CalendarFormat::JCal => "jcal",
+            CalendarFormat::XCal => "xcal",
+        }
+    }
+
+    /// Find the format with the given MIME type.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        [
+            CalendarFormat::ICalendar,
+            CalendarFormat::JCal,
+            CalendarFormat::XCal,
+        ]
+        .into_iter()
+        .find(|format| format.content_type().eq_ignore_ascii_case(content_type.trim()))
+    }
+}
+
+impl FromStr for CalendarFormat {
+    type Err = anyhow::Error;
+
+    /// Parses `ics`, `jcal`, or `xcal`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ics" | "ical" | "icalendar" => Ok(CalendarFormat::ICalendar),
+            "jcal" => Ok(CalendarFormat::JCal),
+            "xcal" => Ok(CalendarFormat::XCal),
+            _ => bail!("Unknown calendar format {:?}", s),
+        }
+    }
+}
 
 /// Convert character birthdays into ICalendar format.
 pub trait BirthdayICalendar {
     /// Returns an ICalendar-formatted string.
-    fn to_ics(&self, now: &OffsetDateTime) -> Result<String>;
+    fn to_ics(&self, now: &OffsetDateTime) -> Result<String> {
+        self.to_ics_with_options(now, &CalendarOptions::default())
+    }
+
+    /// Returns an ICalendar-formatted string with the given calendar-level properties.
+    fn to_ics_with_options(&self, now: &OffsetDateTime, options: &CalendarOptions)
+        -> Result<String>;
+
+    /// Returns a jCal document with the given calendar-level properties.
+    fn to_jcal_with_options(
+        &self,
+        now: &OffsetDateTime,
+        options: &CalendarOptions,
+    ) -> Result<serde_json::Value> {
+        jcal::ics_to_jcal(&self.to_ics_with_options(now, options)?)
+    }
+
+    /// Returns an xCal document with the given calendar-level properties.
+    fn to_xcal_with_options(&self, now: &OffsetDateTime, options: &CalendarOptions)
+        -> Result<String> {
+        xcal::ics_to_xcal(&self.to_ics_with_options(now, options)?)
+    }
+
+    /// Returns the calendar written in the given format.
+    fn to_format_with_options(
+        &self,
+        format: CalendarFormat,
+        now: &OffsetDateTime,
+        options: &CalendarOptions,
+    ) -> Result<String> {
+        match format {
+            CalendarFormat::ICalendar => self.to_ics_with_options(now, options),
+            CalendarFormat::JCal => Ok(self.to_jcal_with_options(now, options)?.to_string()),
+            CalendarFormat::XCal => self.to_xcal_with_options(now, options),
+        }
+    }
 }
 
 impl BirthdayICalendar for Vec<Character> {
-    fn to_ics(&self, now: &OffsetDateTime) -> Result<String> {
+    fn to_ics_with_options(
+        &self,
+        now: &OffsetDateTime,
+        options: &CalendarOptions,
+    ) -> Result<String> {
         let mut calendar = ICalendar::new("2.0", "ics-rs");
 
-        for character in self {
-            let bd = character.birthday().next_occurrence(&now.date())?;
+        if let Some(name) = &options.name {
+            calendar.push(Property::new("NAME", escape_text(name.clone())));
+            calendar.push(Property::new("X-WR-CALNAME", escape_text(name.clone())));
+        }
 
-            let mut start = DtStart::new(date_to_dtstamp(&bd));
-            start.append(parameters!("VALUE" => "DATE"));
+        if let Some(description) = &options.description {
+            calendar.push(Description::new(escape_text(description.clone())));
+            calendar.push(Property::new(
+                "X-WR-CALDESC",
+                escape_text(description.clone()),
+            ));
+        }
 
-            let mut end = DtStart::new(date_to_dtstamp(&(bd + Duration::days(1))));
-            end.append(parameters!("VALUE" => "DATE"));
+        if let Some(timezone) = &options.timezone {
+            calendar.push(Property::new("X-WR-TIMEZONE", timezone.clone()));
+        }
 
-            let mut event = Event::new(Uuid::now_v7().to_string(), datetime_to_dtstamp(now));
+        if let Some(interval) = &options.refresh_interval {
+            let mut refresh = Property::new("REFRESH-INTERVAL", duration_to_ical(interval));
+            refresh.append(parameters!("VALUE" => "DURATION"));
+            calendar.push(refresh);
+            calendar.push(Property::new("X-PUBLISHED-TTL", duration_to_ical(interval)));
+        }
+
+        if let Some(color) = &options.color {
+            calendar.push(Property::new("COLOR", color.clone()));
+        }
+
+        let occurrences = if let Some(horizon) = &options.horizon {
+            let (from, to) = horizon.date_range(&now.date())?;
+
+            self.iter()
+                .flat_map(|character| {
+                    character
+                        .birthday()
+                        .occurrences_between(&from, &to)
+                        .into_iter()
+                        .map(move |date| (character, date))
+                })
+                .collect::<Vec<(&Character, Date)>>()
+        } else {
+            self.iter()
+                .map(|character| {
+                    Ok((character, character.birthday().next_occurrence(&now.date())?))
+                })
+                .collect::<Result<Vec<(&Character, Date)>>>()?
+        };
+
+        let zone = match (&options.event_style, &options.timezone) {
+            (EventStyle::Timed { .. }, Some(name)) => {
+                let zone = tzdb::tz_by_name(name)
+                    .ok_or_else(|| anyhow!("Unknown time zone {:?}", name))?;
+                Some((name.as_str(), zone))
+            }
+            _ => None,
+        };
+
+        if let Some((name, zone)) = zone {
+            let first = occurrences.iter().map(|(_, date)| *date).min();
+            let last = occurrences.iter().map(|(_, date)| *date).max();
+
+            if let (Some(first), Some(last)) = (first, last) {
+                calendar.add_timezone(vtimezone(name, zone, first, last + Duration::days(1))?);
+            }
+        }
 
-            event.push(Summary::new(format!("{}'s Birthday", character.name())));
+        for (character, bd) in occurrences {
+            let (start, end) = match options.event_style {
+                EventStyle::AllDay => {
+                    let mut start = DtStart::new(date_to_dtstamp(&bd));
+                    start.append(parameters!("VALUE" => "DATE"));
+
+                    let mut end = DtEnd::new(date_to_dtstamp(&(bd + Duration::days(1))));
+                    end.append(parameters!("VALUE" => "DATE"));
+
+                    (start, end)
+                }
+                EventStyle::Timed { start, duration } => {
+                    let local_start = PrimitiveDateTime::new(bd, start);
+                    let local_end = local_start + duration;
+
+                    if let Some((name, _zone)) = zone {
+                        let mut start = DtStart::new(local_datetime_to_dtstamp(&local_start));
+                        start.add(parameters::TzIDParam::new(name));
+
+                        let mut end = DtEnd::new(local_datetime_to_dtstamp(&local_end));
+                        end.add(parameters::TzIDParam::new(name));
+
+                        (start, end)
+                    } else {
+                        // Without a time zone, events float at the same wall-clock time
+                        // wherever the calendar is viewed.
+                        (
+                            DtStart::new(local_datetime_to_dtstamp(&local_start)),
+                            DtEnd::new(local_datetime_to_dtstamp(&local_end)),
+                        )
+                    }
+                }
+            };
+
+            // Characters read from other calendars may have no URL, so tell them apart by name.
+            let key = if character.url().is_empty() {
+                character.name()
+            } else {
+                character.url()
+            };
+            let uid = Uuid::new_v5(&Uuid::NAMESPACE_URL, format!("{}#{}", key, bd).as_bytes());
+            let dtstamp = options.last_modified.unwrap_or(*now);
+            let mut event = Event::new(uid.to_string(), datetime_to_dtstamp(&dtstamp));
+
+            event.push(Summary::new(escape_text(format!("{}'s Birthday", character.name()))));
             event.push(start);
             event.push(end);
 
+            if !character.url().is_empty() {
+                event.push(URL::new(character.url().to_string()));
+            }
+
             calendar.add_event(event);
         }
 
@@ -55,6 +462,361 @@ fn datetime_to_dtstamp(datetime: &OffsetDateTime) -> String {
     )
 }
 
+/// Read characters back out of an ICalendar-formatted string.
+///
+/// Understands calendars made by `BirthdayICalendar`, as well as generic birthday
+/// calendars whose events are titled like "Name's birthday" or "Birthday: Name".
+/// Each character is returned once, even if the calendar repeats their birthday.
+pub fn parse_ics(input: &str) -> Result<Vec<Character>> {
+    let calendar = ParsedComponent::parse(input)?;
+
+    ensure!(
+        calendar.name == "VCALENDAR",
+        "Input is not an ICalendar file"
+    );
+
+    let mut characters: Vec<Character> = vec![];
+
+    for event in calendar.components.iter().filter(|c| c.name == "VEVENT") {
+        let character = event_to_character(event)?;
+
+        if !characters.contains(&character) {
+            characters.push(character);
+        }
+    }
+
+    Ok(characters)
+}
+
+fn event_to_character(event: &ParsedComponent) -> Result<Character> {
+    let summary = event.property("SUMMARY").context("Event is missing a SUMMARY")?;
+    let dtstart = event.property("DTSTART").context("Event is missing a DTSTART")?;
+    let url = event.property("URL").map(|url| url.value.as_str()).unwrap_or_default();
+
+    let summary = unescape_text(&summary.value);
+    let date = parse_ics_date(&dtstart.value)
+        .with_context(|| format!("Invalid DTSTART for event {:?}", summary))?;
+
+    Ok(Character::new(
+        &name_from_summary(&summary),
+        url,
+        Birthday::from_date(&date),
+    ))
+}
+
+/// A component read from ICalendar text, like a `VCALENDAR` or `VEVENT`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct ParsedComponent {
+    /// Upper-cased component name.
+    pub(crate) name: String,
+    pub(crate) properties: Vec<ParsedProperty>,
+    pub(crate) components: Vec<ParsedComponent>,
+}
+
+/// A property read from ICalendar text, with its value still escaped.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct ParsedProperty {
+    /// Upper-cased property name.
+    pub(crate) name: String,
+    /// Parameters in order, with upper-cased names and unquoted values.
+    pub(crate) parameters: Vec<(String, String)>,
+    pub(crate) value: String,
+}
+
+impl ParsedComponent {
+    /// Parse the first top-level component out of ICalendar text.
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        let mut stack: Vec<ParsedComponent> = vec![];
+
+        for (number, line) in unfold_lines(input).iter().enumerate() {
+            let property = ParsedProperty::parse(line)
+                .with_context(|| format!("Malformed content line {}: {:?}", number + 1, line))?;
+
+            match property.name.as_str() {
+                "BEGIN" => stack.push(ParsedComponent {
+                    name: property.value.to_ascii_uppercase(),
+                    properties: vec![],
+                    components: vec![],
+                }),
+                "END" => {
+                    let component = stack.pop().context("END without a matching BEGIN")?;
+
+                    ensure!(
+                        component.name.eq_ignore_ascii_case(&property.value),
+                        "Expected END:{} but found END:{}",
+                        component.name,
+                        property.value
+                    );
+
+                    match stack.last_mut() {
+                        Some(parent) => parent.components.push(component),
+                        None => return Ok(component),
+                    }
+                }
+                _ => stack
+                    .last_mut()
+                    .context("Property outside of any component")?
+                    .properties
+                    .push(property),
+            }
+        }
+
+        bail!("Input ended before the calendar did")
+    }
+
+    /// Get the first property with the given upper-cased name.
+    pub(crate) fn property(&self, name: &str) -> Option<&ParsedProperty> {
+        self.properties.iter().find(|property| property.name == name)
+    }
+}
+
+impl ParsedProperty {
+    /// Get the value of the parameter with the given upper-cased name.
+    pub(crate) fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Split a content line into its name, parameters, and value.
+    fn parse(line: &str) -> Option<Self> {
+        let mut in_quotes = false;
+        let mut segments = vec![];
+        let mut segment_start = 0;
+
+        for (index, ch) in line.char_indices() {
+            match ch {
+                '"' => in_quotes = !in_quotes,
+                ';' if !in_quotes => {
+                    segments.push(&line[segment_start..index]);
+                    segment_start = index + 1;
+                }
+                ':' if !in_quotes => {
+                    segments.push(&line[segment_start..index]);
+
+                    let name = segments.first()?.to_ascii_uppercase();
+                    let parameters = segments[1..]
+                        .iter()
+                        .map(|parameter| {
+                            let (key, value) = parameter.split_once('=')?;
+                            Some((key.to_ascii_uppercase(), value.trim_matches('"').to_string()))
+                        })
+                        .collect::<Option<Vec<_>>>()?;
+
+                    return Some(Self {
+                        name,
+                        parameters,
+                        value: line[index + 1..].to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+/// Join folded lines back together, dropping empty lines.
+fn unfold_lines(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+
+    for line in input.lines() {
+        if let Some(continuation) = line.strip_prefix([' ', '\t']) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(continuation);
+                continue;
+            }
+        }
+
+        if !line.trim().is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+
+    lines
+}
+
+pub(crate) fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(ch);
+        }
+    }
+
+    unescaped
+}
+
+/// Parse the date part of a `DATE` or `DATE-TIME` value.
+fn parse_ics_date(value: &str) -> Result<Date> {
+    let date = value.get(..8).context("Date is too short")?;
+    Date::parse(date, format_description!("[year][month][day]"))
+        .with_context(|| format!("Invalid date {:?}", value))
+}
+
+fn name_from_summary(summary: &str) -> String {
+    let summary = summary.trim();
+    let lowercase = summary.to_lowercase();
+
+    for suffix in ["'s birthday", "\u{2019}s birthday", " birthday"] {
+        if lowercase.ends_with(suffix) && lowercase.len() == summary.len() {
+            return summary[..summary.len() - suffix.len()].trim().to_string();
+        }
+    }
+
+    for prefix in ["birthday: ", "birthday of "] {
+        if lowercase.starts_with(prefix) {
+            return summary[prefix.len()..].trim().to_string();
+        }
+    }
+
+    summary.to_string()
+}
+
+/// Build a `VTIMEZONE` describing the offsets `zone` observes between two dates.
+///
+/// Transitions are found by walking the zone a day at a time and bisecting each change,
+/// so both historical transitions and the zone's recurring rule are covered.
+fn vtimezone<'a>(
+    name: &'a str,
+    zone: TimeZoneRef<'static>,
+    from: Date,
+    to: Date,
+) -> Result<TimeZone<'a>> {
+    const DAY: i64 = 24 * 60 * 60;
+
+    let start = PrimitiveDateTime::new(from - Duration::days(1), Time::MIDNIGHT)
+        .assume_utc()
+        .unix_timestamp();
+    let end = PrimitiveDateTime::new(to + Duration::days(1), Time::MIDNIGHT)
+        .assume_utc()
+        .unix_timestamp();
+
+    let initial = zone.find_local_time_type(start)?;
+
+    let mut timezone = match observance(start, initial, initial)? {
+        ZoneTime::Standard(standard) => TimeZone::standard(name, standard),
+        ZoneTime::Daylight(daylight) => TimeZone::daylight(name, daylight),
+    };
+
+    let mut previous = initial;
+    let mut time = start;
+
+    while time < end {
+        let next_time = (time + DAY).min(end);
+        let next = zone.find_local_time_type(next_time)?;
+
+        if !same_local_time_type(previous, next) {
+            // Find the first second observing the new local time type.
+            let (mut low, mut high) = (time, next_time);
+            while high - low > 1 {
+                let mid = low + (high - low) / 2;
+                if same_local_time_type(previous, zone.find_local_time_type(mid)?) {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+
+            let onset_type = zone.find_local_time_type(high)?;
+
+            match observance(high, previous, onset_type)? {
+                ZoneTime::Standard(standard) => timezone.add_standard(standard),
+                ZoneTime::Daylight(daylight) => timezone.add_daylight(daylight),
+            }
+
+            previous = onset_type;
+            time = high;
+        } else {
+            time = next_time;
+        }
+    }
+
+    Ok(timezone)
+}
+
+enum ZoneTime<'a> {
+    Standard(Standard<'a>),
+    Daylight(Daylight<'a>),
+}
+
+/// Describe switching from one local time type to another at a Unix timestamp.
+fn observance<'a>(onset: i64, from: &LocalTimeType, to: &LocalTimeType) -> Result<ZoneTime<'a>> {
+    // DTSTART is expressed in the local time that was in effect before the onset.
+    let onset_local = OffsetDateTime::from_unix_timestamp(onset)?
+        .to_offset(UtcOffset::from_whole_seconds(from.ut_offset())?);
+    let dtstart = local_datetime_to_dtstamp(&PrimitiveDateTime::new(
+        onset_local.date(),
+        onset_local.time(),
+    ));
+
+    let offset_from = offset_to_ical(from.ut_offset());
+    let offset_to = offset_to_ical(to.ut_offset());
+    let tzname = TzName::new(to.time_zone_designation().to_string());
+
+    if to.is_dst() {
+        let mut daylight = Daylight::new(dtstart, offset_from, offset_to);
+        daylight.push(tzname);
+        Ok(ZoneTime::Daylight(daylight))
+    } else {
+        let mut standard = Standard::new(dtstart, offset_from, offset_to);
+        standard.push(tzname);
+        Ok(ZoneTime::Standard(standard))
+    }
+}
+
+fn same_local_time_type(a: &LocalTimeType, b: &LocalTimeType) -> bool {
+    a.ut_offset() == b.ut_offset()
+        && a.is_dst() == b.is_dst()
+        && a.time_zone_designation() == b.time_zone_designation()
+}
+
+fn offset_to_ical(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.unsigned_abs();
+    let (hours, minutes, seconds) = (offset / 3600, offset % 3600 / 60, offset % 60);
+
+    if seconds == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, seconds)
+    }
+}
+
+fn local_datetime_to_dtstamp(datetime: &PrimitiveDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day(),
+        datetime.hour(),
+        datetime.minute(),
+        datetime.second()
+    )
+}
+
+fn duration_to_ical(duration: &Duration) -> String {
+    let seconds = duration.whole_seconds().max(0);
+
+    if seconds % 3600 == 0 {
+        format!("PT{}H", seconds / 3600)
+    } else if seconds % 60 == 0 {
+        format!("PT{}M", seconds / 60)
+    } else {
+        format!("PT{}S", seconds)
+    }
+}
+
 fn date_to_dtstamp(date: &Date) -> String {
     format!(
         "{:04}{:02}{:02}",
@@ -63,3 +825,309 @@ fn date_to_dtstamp(date: &Date) -> String {
         date.day()
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Duration, Month, OffsetDateTime, Time};
+
+    use super::{
+        add_years, parse_date, parse_ics, BirthdayICalendar, CalendarFormat, CalendarOptions, EventStyle,
+        Horizon,
+    };
+    use crate::{Birthday, Character};
+
+    fn characters() -> Vec<Character> {
+        vec![Character::new(
+            "Frieren",
+            "https://anilist.co/character/176754",
+            Birthday::new(Month::March, 8),
+        )]
+    }
+
+    #[test]
+    fn to_ics_omits_unset_calendar_properties() {
+        let ics = characters().to_ics(&OffsetDateTime::UNIX_EPOCH).unwrap();
+
+        assert!(!ics.contains("X-WR-CALNAME"));
+        assert!(!ics.contains("REFRESH-INTERVAL"));
+        assert!(!ics.contains("COLOR"));
+    }
+
+    #[test]
+    fn to_ics_is_stable_given_last_modified() {
+        let options = CalendarOptions {
+            last_modified: Some(OffsetDateTime::UNIX_EPOCH),
+            ..CalendarOptions::default()
+        };
+        let now = OffsetDateTime::UNIX_EPOCH + Duration::hours(1);
+
+        let first = characters().to_ics_with_options(&now, &options).unwrap();
+        let second = characters()
+            .to_ics_with_options(&(now + Duration::minutes(5)), &options)
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.contains("DTSTAMP:19700101T000000\r\n"));
+    }
+
+    #[test]
+    fn to_ics_with_options_emits_calendar_properties() {
+        let options = CalendarOptions {
+            timezone: Some("America/Denver".to_string()),
+            refresh_interval: Some(Duration::minutes(15)),
+            color: Some("palevioletred".to_string()),
+            ..CalendarOptions::for_user("Owldown")
+        };
+
+        let ics = characters()
+            .to_ics_with_options(&OffsetDateTime::UNIX_EPOCH, &options)
+            .unwrap();
+
+        assert!(ics.contains("NAME:Owldown's waifu birthdays\r\n"));
+        assert!(ics.contains("X-WR-CALNAME:Owldown's waifu birthdays\r\n"));
+        assert!(ics.contains("X-WR-CALDESC:Birthdays of Owldown's favorite characters on AniList\r\n"));
+        assert!(ics.contains("X-WR-TIMEZONE:America/Denver\r\n"));
+        assert!(ics.contains("REFRESH-INTERVAL;VALUE=DURATION:PT15M\r\n"));
+        assert!(ics.contains("X-PUBLISHED-TTL:PT15M\r\n"));
+        assert!(ics.contains("COLOR:palevioletred\r\n"));
+    }
+
+    #[test]
+    fn event_style_from_str() {
+        assert_eq!("all-day".parse::<EventStyle>().unwrap(), EventStyle::AllDay);
+        assert_eq!("timed".parse::<EventStyle>().unwrap(), EventStyle::full_day());
+        assert_eq!(
+            "09:30".parse::<EventStyle>().unwrap(),
+            EventStyle::at(Time::from_hms(9, 30, 0).unwrap())
+        );
+        assert!("25:00".parse::<EventStyle>().is_err());
+        assert!("sometime".parse::<EventStyle>().is_err());
+    }
+
+    #[test]
+    fn to_ics_with_timed_events_in_timezone() {
+        let options = CalendarOptions {
+            timezone: Some("America/Denver".to_string()),
+            event_style: EventStyle::full_day(),
+            ..Default::default()
+        };
+
+        let ics = characters()
+            .to_ics_with_options(&OffsetDateTime::UNIX_EPOCH, &options)
+            .unwrap();
+
+        assert!(ics.contains("BEGIN:VTIMEZONE\r\nTZID:America/Denver\r\n"));
+        assert!(ics.contains("TZOFFSETTO:-0700\r\n"));
+        assert!(ics.contains("DTSTART;TZID=America/Denver:19700308T000000\r\n"));
+        assert!(ics.contains("DTEND;TZID=America/Denver:19700308T235900\r\n"));
+    }
+
+    #[test]
+    fn to_ics_with_timed_events_without_timezone_float() {
+        let options = CalendarOptions {
+            event_style: EventStyle::at(Time::from_hms(9, 0, 0).unwrap()),
+            ..Default::default()
+        };
+
+        let ics = characters()
+            .to_ics_with_options(&OffsetDateTime::UNIX_EPOCH, &options)
+            .unwrap();
+
+        assert!(!ics.contains("BEGIN:VTIMEZONE"));
+        assert!(ics.contains("DTSTART:19700308T090000\r\n"));
+    }
+
+    #[test]
+    fn to_ics_with_timed_events_includes_dst_transitions() {
+        let options = CalendarOptions {
+            timezone: Some("America/Denver".to_string()),
+            event_style: EventStyle::full_day(),
+            ..Default::default()
+        };
+
+        let characters = vec![
+            Character::new("Frieren", "", Birthday::new(Month::March, 8)),
+            Character::new("Fern", "", Birthday::new(Month::December, 1)),
+        ];
+
+        let now = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap();
+        let ics = characters.to_ics_with_options(&now, &options).unwrap();
+
+        assert!(ics.contains(
+            "BEGIN:DAYLIGHT\r\nDTSTART:20240310T020000\r\nTZOFFSETFROM:-0700\r\nTZOFFSETTO:-0600\r\nTZNAME:MDT\r\nEND:DAYLIGHT\r\n"
+        ));
+        assert!(ics.contains(
+            "BEGIN:STANDARD\r\nDTSTART:20241103T020000\r\nTZOFFSETFROM:-0600\r\nTZOFFSETTO:-0700\r\nTZNAME:MST\r\nEND:STANDARD\r\n"
+        ));
+    }
+
+    #[test]
+    fn to_ics_gives_characters_without_urls_distinct_uids() {
+        let characters = vec![
+            Character::new("Frieren", "", Birthday::new(Month::March, 8)),
+            Character::new("Fern", "", Birthday::new(Month::March, 8)),
+        ];
+
+        let ics = characters.to_ics(&OffsetDateTime::UNIX_EPOCH).unwrap();
+        let mut uids: Vec<_> = ics.lines().filter(|line| line.starts_with("UID:")).collect();
+        uids.dedup();
+
+        assert_eq!(uids.len(), 2);
+    }
+
+    #[test]
+    fn to_ics_all_day_events_have_an_end() {
+        let ics = characters().to_ics(&OffsetDateTime::UNIX_EPOCH).unwrap();
+
+        assert!(ics.contains("DTSTART;VALUE=DATE:19700308\r\n"));
+        assert!(ics.contains("DTEND;VALUE=DATE:19700309\r\n"));
+    }
+
+    #[test]
+    fn horizon_from_params() {
+        let today = Date::from_calendar_date(2024, Month::February, 29).unwrap();
+
+        assert_eq!(Horizon::from_params(&today, None, None, None).unwrap(), None);
+        assert_eq!(
+            Horizon::from_params(&today, Some(3), None, None).unwrap(),
+            Some(Horizon::Years(3))
+        );
+        assert_eq!(
+            Horizon::from_params(&today, None, Some(parse_date("2025-06-01").unwrap()), None)
+                .unwrap(),
+            Some(Horizon::Range {
+                from: parse_date("2025-06-01").unwrap(),
+                to: parse_date("2026-05-31").unwrap(),
+            })
+        );
+        assert!(Horizon::from_params(&today, Some(1), Some(today), None).is_err());
+        assert!(Horizon::from_params(&today, Some(1000), None, None).is_err());
+        assert!(Horizon::from_params(&today, Some(2_147_483_000), None, None).is_err());
+        assert!(add_years(&today, i32::MAX).is_err());
+        assert!(Horizon::from_params(
+            &today,
+            None,
+            Some(parse_date("2025-06-01").unwrap()),
+            Some(parse_date("2025-05-01").unwrap())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn horizon_years_date_range() {
+        let today = Date::from_calendar_date(2024, Month::February, 29).unwrap();
+
+        assert_eq!(
+            Horizon::Years(1).date_range(&today).unwrap(),
+            (today, parse_date("2025-02-28").unwrap())
+        );
+        assert_eq!(
+            Horizon::Years(4).date_range(&today).unwrap(),
+            (today, parse_date("2028-02-28").unwrap())
+        );
+    }
+
+    #[test]
+    fn to_ics_with_horizon_emits_every_occurrence() {
+        let characters = vec![
+            Character::new("Frieren", "", Birthday::new(Month::March, 8)),
+            Character::new("Leap", "", Birthday::new(Month::February, 29)),
+        ];
+
+        let options = CalendarOptions {
+            horizon: Some(Horizon::Range {
+                from: parse_date("2024-01-01").unwrap(),
+                to: parse_date("2028-12-31").unwrap(),
+            }),
+            ..Default::default()
+        };
+
+        let ics = characters
+            .to_ics_with_options(&OffsetDateTime::UNIX_EPOCH, &options)
+            .unwrap();
+
+        assert_eq!(ics.matches("SUMMARY:Frieren's Birthday").count(), 5);
+        assert_eq!(ics.matches("SUMMARY:Leap's Birthday").count(), 2);
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240229\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20280229\r\n"));
+    }
+
+    #[test]
+    fn parse_ics_round_trip() {
+        let characters = vec![
+            Character::new(
+                "Frieren",
+                "https://anilist.co/character/176754",
+                Birthday::new(Month::March, 8),
+            ),
+            Character::new("Stark; the Warrior", "", Birthday::new(Month::February, 29)),
+        ];
+
+        let options = CalendarOptions {
+            horizon: Some(Horizon::Years(8)),
+            ..CalendarOptions::for_user("Owldown")
+        };
+
+        let ics = characters
+            .to_ics_with_options(&OffsetDateTime::UNIX_EPOCH, &options)
+            .unwrap();
+
+        assert_eq!(parse_ics(&ics).unwrap(), characters);
+    }
+
+    #[test]
+    fn parse_ics_generic_birthday_calendar() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            PRODID:-//Google Inc//Google Calendar 70.9054//EN\r\n\
+            X-WR-CALNAME:Birthdays\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;VALUE=DATE:20250313\r\n\
+            RRULE:FREQ=YEARLY\r\n\
+            SUMMARY:Himmel's birthday\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;TZID=\"Europe/Berlin\":16041201T000000\r\n\
+            SUMMARY:Birthday: Fern \r\n Schwert\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+
+        let characters = parse_ics(ics).unwrap();
+
+        assert_eq!(
+            characters,
+            vec![
+                Character::new("Himmel", "", Birthday::new(Month::March, 13)),
+                Character::new("Fern Schwert", "", Birthday::new(Month::December, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_ics_rejects_non_calendars() {
+        assert!(parse_ics("hello").is_err());
+        assert!(parse_ics(
+            "BEGIN:VCALENDAR\nBEGIN:VEVENT\nSUMMARY:Nobody\nEND:VEVENT\nEND:VCALENDAR\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn calendar_format_from_content_type() {
+        assert_eq!(
+            CalendarFormat::from_content_type("application/calendar+json"),
+            Some(CalendarFormat::JCal)
+        );
+        assert_eq!(CalendarFormat::from_content_type("text/html"), None);
+    }
+
+    #[test]
+    fn to_jcal_with_options_wraps_ics() {
+        let jcal = characters()
+            .to_jcal_with_options(&OffsetDateTime::UNIX_EPOCH, &CalendarOptions::default())
+            .unwrap();
+
+        assert_eq!(jcal[0], "vcalendar");
+        assert_eq!(jcal[2][0][0], "vevent");
+    }
+}
@@ -0,0 +1,216 @@
+//! Tools for making jCal ([RFC 7265](https://www.rfc-editor.org/rfc/rfc7265)) data.
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+
+use super::{unescape_text, ParsedComponent, ParsedProperty};
+
+/// Convert ICalendar-formatted text into a jCal document.
+pub fn ics_to_jcal(input: &str) -> Result<Value> {
+    let calendar = ParsedComponent::parse(input).context("Failed to parse ICalendar text")?;
+
+    component_to_jcal(&calendar)
+}
+
+fn component_to_jcal(component: &ParsedComponent) -> Result<Value> {
+    let properties = component
+        .properties
+        .iter()
+        .map(property_to_jcal)
+        .collect::<Result<Vec<Value>>>()?;
+    let components = component
+        .components
+        .iter()
+        .map(component_to_jcal)
+        .collect::<Result<Vec<Value>>>()?;
+
+    Ok(json!([component.name.to_lowercase(), properties, components]))
+}
+
+fn property_to_jcal(property: &ParsedProperty) -> Result<Value> {
+    let value_type = value_type(property);
+
+    let parameters: Map<String, Value> = property
+        .parameters
+        .iter()
+        .filter(|(name, _)| name != "VALUE")
+        .map(|(name, value)| (name.to_lowercase(), Value::String(value.clone())))
+        .collect();
+
+    let value = jcal_value(&value_type, &property.value)
+        .with_context(|| format!("Invalid {} value for {}", value_type, property.name))?;
+
+    Ok(json!([
+        property.name.to_lowercase(),
+        parameters,
+        value_type,
+        value
+    ]))
+}
+
+/// Get the value type of a property, from its `VALUE` parameter or its name.
+pub(crate) fn value_type(property: &ParsedProperty) -> String {
+    if let Some(value_type) = property.parameter("VALUE") {
+        return value_type.to_lowercase();
+    }
+
+    match property.name.as_str() {
+        "DTSTART" | "DTEND" | "DTSTAMP" | "CREATED" | "LAST-MODIFIED" | "RECURRENCE-ID"
+        | "EXDATE" | "RDATE" | "DUE" | "COMPLETED" => "date-time",
+        "TZOFFSETFROM" | "TZOFFSETTO" => "utc-offset",
+        "URL" | "TZURL" | "ATTACH" => "uri",
+        "DURATION" | "REFRESH-INTERVAL" | "X-PUBLISHED-TTL" => "duration",
+        "SEQUENCE" | "PRIORITY" | "PERCENT-COMPLETE" | "REPEAT" => "integer",
+        "RRULE" | "EXRULE" => "recur",
+        _ => "text",
+    }
+    .to_string()
+}
+
+/// Convert an ICalendar value into the JSON type jCal gives values of its type.
+///
+/// See [RFC 7265 §3.6](https://www.rfc-editor.org/rfc/rfc7265#section-3.6).
+fn jcal_value(value_type: &str, value: &str) -> Result<Value> {
+    match value_type {
+        "integer" => Ok(json!(value.parse::<i64>()?)),
+        "float" => Ok(json!(value.parse::<f64>()?)),
+        "boolean" => Ok(json!(value.eq_ignore_ascii_case("TRUE"))),
+        "recur" => recur_to_jcal(value),
+        _ => Ok(Value::String(format_value(value_type, value)?)),
+    }
+}
+
+/// Convert a recurrence rule, like `FREQ=YEARLY;BYMONTH=3`, into a jCal recur object.
+///
+/// Parts listing several values become arrays, and numeric parts become numbers.
+fn recur_to_jcal(value: &str) -> Result<Value> {
+    let mut recur = Map::new();
+
+    for part in value.split(';').filter(|part| !part.is_empty()) {
+        let (name, value) = part
+            .split_once('=')
+            .with_context(|| format!("Recurrence rule part {:?} has no value", part))?;
+        let name = name.to_lowercase();
+
+        let values = value
+            .split(',')
+            .map(|value| match name.as_str() {
+                "until" => {
+                    let value_type = if value.len() > 8 { "date-time" } else { "date" };
+                    Ok(Value::String(format_value(value_type, value)?))
+                }
+                "count" | "interval" | "bysecond" | "byminute" | "byhour" | "bymonthday"
+                | "byyearday" | "byweekno" | "bymonth" | "bysetpos" => Ok(json!(value
+                    .parse::<i64>()
+                    .with_context(|| format!("Invalid number {:?} in {}", value, name))?)),
+                _ => Ok(Value::String(value.to_string())),
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        let value = match <[Value; 1]>::try_from(values) {
+            Ok([value]) => value,
+            Err(values) => Value::Array(values),
+        };
+
+        recur.insert(name, value);
+    }
+
+    Ok(Value::Object(recur))
+}
+
+/// Reformat an ICalendar value the way jCal and xCal write it.
+pub(crate) fn format_value(value_type: &str, value: &str) -> Result<String> {
+    match value_type {
+        "date" => {
+            let date = value.get(..8).context("Date is too short")?;
+            Ok(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..8]))
+        }
+        "date-time" => {
+            let date = format_value("date", value)?;
+            let time = value.get(9..15).context("Date-time is too short")?;
+            let utc = if value.ends_with('Z') { "Z" } else { "" };
+            Ok(format!(
+                "{}T{}:{}:{}{}",
+                date,
+                &time[..2],
+                &time[2..4],
+                &time[4..6],
+                utc
+            ))
+        }
+        "utc-offset" => {
+            let sign = value.get(..1).context("Offset is too short")?;
+            let hours = value.get(1..3).context("Offset is too short")?;
+            let minutes = value.get(3..5).context("Offset is too short")?;
+            match value.get(5..7) {
+                Some(seconds) => Ok(format!("{}{}:{}:{}", sign, hours, minutes, seconds)),
+                None => Ok(format!("{}{}:{}", sign, hours, minutes)),
+            }
+        }
+        "text" => Ok(unescape_text(value)),
+        _ => Ok(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::ics_to_jcal;
+
+    #[test]
+    fn ics_to_jcal_converts_values() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            X-WR-CALNAME:Owldown's waifu birthdays\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:1\r\n\
+            SEQUENCE:2\r\n\
+            RRULE:FREQ=YEARLY;BYMONTH=3;BYMONTHDAY=8;BYDAY=MO,TU;UNTIL=20300308\r\n\
+            DTSTAMP:19700101T000000Z\r\n\
+            SUMMARY:Stark\\; the Warrior's Birthday\r\n\
+            DTSTART;VALUE=DATE:19700308\r\n\
+            DTEND;TZID=America/Denver:19700308T235900\r\n\
+            URL:https://anilist.co/character/176754\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+
+        let jcal = ics_to_jcal(ics).unwrap();
+
+        assert_eq!(
+            jcal,
+            json!([
+                "vcalendar",
+                [
+                    ["version", {}, "text", "2.0"],
+                    ["x-wr-calname", {}, "text", "Owldown's waifu birthdays"],
+                ],
+                [[
+                    "vevent",
+                    [
+                        ["uid", {}, "text", "1"],
+                        ["sequence", {}, "integer", 2],
+                        [
+                            "rrule",
+                            {},
+                            "recur",
+                            {
+                                "freq": "YEARLY",
+                                "bymonth": 3,
+                                "bymonthday": 8,
+                                "byday": ["MO", "TU"],
+                                "until": "2030-03-08",
+                            },
+                        ],
+                        ["dtstamp", {}, "date-time", "1970-01-01T00:00:00Z"],
+                        ["summary", {}, "text", "Stark; the Warrior's Birthday"],
+                        ["dtstart", {}, "date", "1970-03-08"],
+                        ["dtend", {"tzid": "America/Denver"}, "date-time", "1970-03-08T23:59:00"],
+                        ["url", {}, "uri", "https://anilist.co/character/176754"],
+                    ],
+                    [],
+                ]],
+            ])
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Tools for making xCal ([RFC 6321](https://www.rfc-editor.org/rfc/rfc6321)) data.
+
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+
+use super::{
+    jcal::{format_value, value_type},
+    ParsedComponent, ParsedProperty,
+};
+use crate::escape_xml;
+
+/// Convert ICalendar-formatted text into an xCal document.
+pub fn ics_to_xcal(input: &str) -> Result<String> {
+    let calendar = ParsedComponent::parse(input).context("Failed to parse ICalendar text")?;
+
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    xml.push_str(r#"<icalendar xmlns="urn:ietf:params:xml:ns:icalendar-2.0">"#);
+    write_component(&mut xml, &calendar)?;
+    xml.push_str("</icalendar>");
+
+    Ok(xml)
+}
+
+fn write_component(xml: &mut String, component: &ParsedComponent) -> Result<()> {
+    let name = component.name.to_lowercase();
+
+    write!(xml, "<{}>", name)?;
+
+    if !component.properties.is_empty() {
+        xml.push_str("<properties>");
+        for property in &component.properties {
+            write_property(xml, property)?;
+        }
+        xml.push_str("</properties>");
+    }
+
+    if !component.components.is_empty() {
+        xml.push_str("<components>");
+        for subcomponent in &component.components {
+            write_component(xml, subcomponent)?;
+        }
+        xml.push_str("</components>");
+    }
+
+    write!(xml, "</{}>", name)?;
+
+    Ok(())
+}
+
+fn write_property(xml: &mut String, property: &ParsedProperty) -> Result<()> {
+    let name = property.name.to_lowercase();
+    let value_type = value_type(property);
+    let value = format_value(&value_type, &property.value)
+        .with_context(|| format!("Invalid {} value for {}", value_type, property.name))?;
+
+    write!(xml, "<{}>", name)?;
+
+    let parameters: Vec<&(String, String)> = property
+        .parameters
+        .iter()
+        .filter(|(name, _)| name != "VALUE")
+        .collect();
+
+    if !parameters.is_empty() {
+        xml.push_str("<parameters>");
+        for (parameter, parameter_value) in parameters {
+            let parameter = parameter.to_lowercase();
+            write!(
+                xml,
+                "<{0}><text>{1}</text></{0}>",
+                parameter,
+                escape_xml(parameter_value)
+            )?;
+        }
+        xml.push_str("</parameters>");
+    }
+
+    write!(xml, "<{0}>{1}</{0}>", value_type, escape_xml(&value))?;
+    write!(xml, "</{}>", name)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ics_to_xcal;
+
+    #[test]
+    fn ics_to_xcal_converts_values() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+            VERSION:2.0\r\n\
+            BEGIN:VEVENT\r\n\
+            SUMMARY:Frieren & Fern's Birthday\r\n\
+            DTSTART;TZID=America/Denver:19700308T000000\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+
+        assert_eq!(
+            ics_to_xcal(ics).unwrap(),
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<icalendar xmlns="urn:ietf:params:xml:ns:icalendar-2.0">"#,
+                "<vcalendar>",
+                "<properties><version><text>2.0</text></version></properties>",
+                "<components><vevent><properties>",
+                "<summary><text>Frieren &amp; Fern&apos;s Birthday</text></summary>",
+                "<dtstart><parameters><tzid><text>America/Denver</text></tzid></parameters>",
+                "<date-time>1970-03-08T00:00:00</date-time></dtstart>",
+                "</properties></vevent></components>",
+                "</vcalendar>",
+                "</icalendar>",
+            )
+        );
+    }
+}
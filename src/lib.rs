@@ -3,15 +3,22 @@
 #[cfg(feature = "http")]
 pub mod http;
 
+#[cfg(feature = "export")]
+pub mod export;
+
+pub mod feed;
+
 #[cfg(feature = "ics")]
 pub mod ics;
 
+#[cfg(feature = "vcard")]
+pub mod vcard;
+
 use core::fmt;
 
 use anyhow::{ensure, Context, Result, bail};
 use graphql_client::{GraphQLQuery, Response};
-use reqwest;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use time::{Date, Duration, Month, OffsetDateTime, Time};
 
 #[derive(GraphQLQuery)]
@@ -23,7 +30,7 @@ use time::{Date, Duration, Month, OffsetDateTime, Time};
 struct BirthdaysQuery;
 
 /// A `Month` and day pair.
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Birthday {
     month: Month,
     day: u8,
@@ -75,7 +82,8 @@ impl Birthday {
             bd_date_this_year
         } else {
             // Birthday has already happened this year
-            self.to_date(today).with_context(|| {
+            let next_year = Date::from_calendar_date(today.year() + 1, Month::January, 1)?;
+            self.to_date(&next_year).with_context(|| {
                 format!(
                     "Failed to convert birthday into date with year {}",
                     today.year() + 1
@@ -96,8 +104,9 @@ impl Birthday {
         let current_year = today.year();
         let occurrence_year =
             if self.month() == Month::February && self.day() == 29 {
-                let til_leap_year = 4 - (current_year % 4);
-                today.year() + til_leap_year
+                (current_year..)
+                    .find(|year| time::util::is_leap_year(*year))
+                    .unwrap_or(current_year)
             } else {
                 today.year()
             };
@@ -111,6 +120,35 @@ impl Birthday {
         Ok(date)
     }
 
+    /// Get every `Date` this birthday occurs on between two dates, inclusive.
+    ///
+    /// February 29th birthdays only occur in leap years.
+    pub fn occurrences_between(&self, from: &Date, to: &Date) -> Vec<Date> {
+        (from.year()..=to.year())
+            .filter_map(|year| Date::from_calendar_date(year, self.month, self.day).ok())
+            .filter(|date| from <= date && date <= to)
+            .collect()
+    }
+
+    /// Find how many years apart this birthday's occurrences are from `first` on,
+    /// and the last one before they stop being that far apart, if they do within 400 years.
+    ///
+    /// For formats that can only repeat an event at a fixed interval. February 29th
+    /// birthdays repeat every four years, until a century year that isn't a leap year.
+    pub fn repetition(&self, first: &Date) -> Result<(i32, Option<Date>)> {
+        let last = Date::from_calendar_date(first.year() + 400, Month::December, 31)?;
+        let occurrences = self.occurrences_between(first, &last);
+
+        let years_apart = |pair: &[Date]| pair[1].year() - pair[0].year();
+        let interval = occurrences.windows(2).next().map_or(1, years_apart);
+        let until = occurrences
+            .windows(2)
+            .find(|pair| years_apart(pair) != interval)
+            .map(|pair| pair[0]);
+
+        Ok((interval, until))
+    }
+
     /// Calculate the `Duration` between now and this birthday.
     pub fn til_next(&self, now: &OffsetDateTime) -> Duration {
         let next_date = self.next_occurrence(&now.date()).unwrap();
@@ -131,10 +169,12 @@ impl fmt::Display for Birthday {
 }
 
 /// A name and birthday pair.
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Character {
     name: String,
     url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
     birthday: Birthday,
 }
 
@@ -144,21 +184,56 @@ impl Character {
         Self {
             name: name.to_string(),
             url: url.to_string(),
+            image: None,
             birthday,
         }
     }
 
+    /// Set the URL of this character's picture.
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.image = Some(image.to_string());
+        self
+    }
+
     /// Get this character's name.
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// Get the URL of this character's AniList page.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the URL of this character's picture, if they have one.
+    pub fn image(&self) -> Option<&str> {
+        self.image.as_deref()
+    }
+
     /// Get this character's birthday
     pub fn birthday(&self) -> Birthday {
         self.birthday
     }
 }
 
+/// Format a `Duration` as an ISO 8601 duration, like `P3DT4H5M6S`.
+pub fn duration_to_iso(dur: &Duration) -> String {
+    let days = dur.whole_days();
+    let hours = (*dur - Duration::days(dur.whole_days())).whole_hours();
+    let minutes = (*dur - Duration::hours(dur.whole_hours())).whole_minutes();
+    let seconds = (*dur - Duration::minutes(dur.whole_minutes())).whole_seconds();
+    format!("P{}DT{}H{}M{}S", days, hours, minutes, seconds)
+}
+
+/// Escape text for use in XML content or attribute values.
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize)]
 pub struct BirthdayCategories {
     pub today: Vec<Character>,
@@ -213,97 +288,165 @@ pub enum Error {
     RateLimited,
 }
 
+/// The URL of AniList's GraphQL API.
+pub const ANILIST_ENDPOINT: &str = "https://graphql.anilist.co";
+
 /// Get the favorite character birthdays for an AniList user.
 ///
 /// Characters are not sorted.
 /// See the `Characters` trait for sort options.
 /// Uses AniList's GraphQL API to fetch data on favorites.
 pub async fn get_waifu_birthdays(username: &str) -> Result<Vec<Character>> {
-    let mut page = 1;
-    let mut has_next_page = true;
+    get_waifu_birthdays_from(ANILIST_ENDPOINT, username).await
+}
 
-    let mut characters = vec![];
+/// Get the favorite character birthdays for an AniList user,
+/// from the GraphQL API at `endpoint` instead of AniList's own.
+pub async fn get_waifu_birthdays_from(endpoint: &str, username: &str) -> Result<Vec<Character>> {
+    get_waifu_birthdays_observed(endpoint, username, |_| {}).await
+}
 
-    while has_next_page {
-        let variables = birthdays_query::Variables {
-            page,
-            user: username.to_string(),
-        };
+/// The outcome of one request to AniList's API.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RequestOutcome {
+    Ok,
+    NotFound,
+    RateLimited,
+    Error,
+}
+
+impl RequestOutcome {
+    fn of<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => RequestOutcome::Ok,
+            Err(err) => match err.downcast_ref::<Error>() {
+                Some(Error::UserNotFound(_)) => RequestOutcome::NotFound,
+                Some(Error::RateLimited) => RequestOutcome::RateLimited,
+                _ => RequestOutcome::Error,
+            },
+        }
+    }
+}
 
-        let request_body = BirthdaysQuery::build_query(variables);
+/// Get the favorite character birthdays for an AniList user, from the GraphQL API at
+/// `endpoint`, calling `observe` with the outcome of each request.
+///
+/// Favorites are requested a page at a time, so there is one request per page.
+pub async fn get_waifu_birthdays_observed(
+    endpoint: &str,
+    username: &str,
+    mut observe: impl FnMut(RequestOutcome),
+) -> Result<Vec<Character>> {
+    let mut page = 1;
+    let mut characters = vec![];
 
-        let client = reqwest::Client::new();
-        let res = client
-            .post("https://graphql.anilist.co")
-            .header("User-Agent", "WaifuCalendar")
-            .json(&request_body)
-            .send()
-            .await?;
+    loop {
+        let result = get_birthdays_page(endpoint, username, page).await;
+        observe(RequestOutcome::of(&result));
 
-        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-            bail!(Error::RateLimited);
+        let (mut page_characters, has_next_page) = result?;
+        characters.append(&mut page_characters);
+
+        if !has_next_page {
+            break;
         }
 
-        let response_body: Response<birthdays_query::ResponseData> = res.json().await?;
-
-        let data = response_body
-            .data
-            .ok_or(Error::BadResponse)
-            .with_context(|| "Missing response data")?;
-
-        let response_page = data
-            .user
-            .ok_or(Error::UserNotFound(username.to_string()))?
-            .favourites
-            .ok_or(Error::BadResponse)
-            .with_context(|| "Missing favourites")?
-            .characters
-            .ok_or(Error::BadResponse)
-            .with_context(|| "Missing characters")?;
-
-        let mut page_characters: Vec<Character> = response_page
-            .nodes
-            .ok_or(Error::BadResponse)
-            .with_context(|| "Missing character nodes")?
-            .iter()
-            .filter_map(|node_result| {
-                let node = node_result.as_ref()?;
-                let dob = node.date_of_birth.as_ref()?;
+        page += 1;
+    }
 
-                let month_opt = dob.month.as_ref();
-                let day_opt = dob.day.as_ref();
+    Ok(characters)
+}
 
-                if month_opt.is_some() && day_opt.is_some() {
-                    let month_num: u8 = month_opt?.to_owned().try_into().ok()?;
-                    let month = Month::try_from(month_num).ok()?;
-                    let day: u8 = day_opt?.to_owned().try_into().ok()?;
+/// Get one page of a user's favorite characters with birthdays,
+/// and whether there is another page after it.
+async fn get_birthdays_page(
+    endpoint: &str,
+    username: &str,
+    page: i64,
+) -> Result<(Vec<Character>, bool)> {
+    let variables = birthdays_query::Variables {
+        page,
+        user: username.to_string(),
+    };
+
+    let request_body = BirthdaysQuery::build_query(variables);
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post(endpoint)
+        .header("User-Agent", "WaifuCalendar")
+        .json(&request_body)
+        .send()
+        .await?;
+
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        bail!(Error::RateLimited);
+    }
 
-                    let birthday = Birthday::new(month, day);
+    let response_body: Response<birthdays_query::ResponseData> = res.json().await?;
 
-                    let name = node.name.as_ref()?.full.as_ref()?.to_string();
+    let data = response_body
+        .data
+        .ok_or(Error::BadResponse)
+        .with_context(|| "Missing response data")?;
 
-                    let url = node.site_url.as_ref()?.to_string();
+    let response_page = data
+        .user
+        .ok_or(Error::UserNotFound(username.to_string()))?
+        .favourites
+        .ok_or(Error::BadResponse)
+        .with_context(|| "Missing favourites")?
+        .characters
+        .ok_or(Error::BadResponse)
+        .with_context(|| "Missing characters")?;
 
-                    let character = Character { name, url, birthday };
+    let page_characters: Vec<Character> = response_page
+        .nodes
+        .ok_or(Error::BadResponse)
+        .with_context(|| "Missing character nodes")?
+        .iter()
+        .filter_map(|node_result| {
+            let node = node_result.as_ref()?;
+            let dob = node.date_of_birth.as_ref()?;
 
-                    Some(character)
-                } else {
-                    None
-                }
-            })
-            .collect();
+            let month_opt = dob.month.as_ref();
+            let day_opt = dob.day.as_ref();
 
-            characters.append(&mut page_characters);
+            if month_opt.is_some() && day_opt.is_some() {
+                let month_num: u8 = month_opt?.to_owned().try_into().ok()?;
+                let month = Month::try_from(month_num).ok()?;
+                let day: u8 = day_opt?.to_owned().try_into().ok()?;
 
-            has_next_page =
-                response_page
-                .page_info.ok_or(Error::BadResponse).with_context(|| "Missing page_info")?
-                .has_next_page.ok_or(Error::BadResponse).with_context(|| "Missing has_next_page")?;
+                let birthday = Birthday::new(month, day);
 
-            page += 1;
-        }
+                let name = node.name.as_ref()?.full.as_ref()?.to_string();
 
-    Ok(characters)
+                let url = node.site_url.as_ref()?.to_string();
+
+                let image = node
+                    .image
+                    .as_ref()
+                    .and_then(|image| image.large.as_ref())
+                    .map(|large| large.to_string());
+
+                let character = Character { name, url, image, birthday };
+
+                Some(character)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let has_next_page = response_page
+        .page_info
+        .ok_or(Error::BadResponse)
+        .with_context(|| "Missing page_info")?
+        .has_next_page
+        .ok_or(Error::BadResponse)
+        .with_context(|| "Missing has_next_page")?;
+
+    Ok((page_characters, has_next_page))
 }
 
 #[cfg(test)]
@@ -346,6 +489,16 @@ mod tests {
         assert_eq!(next.day(), bd.day);
     }
 
+    #[test]
+    fn next_occurrence_rolls_over_at_end_of_year() {
+        let bd = Birthday::new(Month::December, 25);
+        let today = Date::from_calendar_date(2024, Month::December, 31).unwrap();
+
+        let next = bd.next_occurrence(&today).unwrap();
+
+        assert_eq!(next, Date::from_calendar_date(2025, Month::December, 25).unwrap());
+    }
+
     #[test]
     fn to_date() {
         let bd = Birthday::new(Month::January, 13);
@@ -366,6 +519,64 @@ mod tests {
         assert_eq!(date.day(), bd.day);
     }
 
+    #[test]
+    fn to_date_leap_year_during_leap_year() {
+        let bd = Birthday::new(Month::February, 29);
+        let date = bd.to_date(&Date::from_calendar_date(2024, Month::January, 1).unwrap()).unwrap();
+
+        assert_eq!(date.year(), 2024);
+    }
+
+    #[test]
+    fn occurrences_between() {
+        let bd = Birthday::new(Month::January, 13);
+        let from = Date::from_calendar_date(2024, Month::January, 14).unwrap();
+        let to = Date::from_calendar_date(2026, Month::January, 13).unwrap();
+
+        let occurrences = bd.occurrences_between(&from, &to);
+
+        assert_eq!(
+            occurrences,
+            vec![
+                Date::from_calendar_date(2025, Month::January, 13).unwrap(),
+                Date::from_calendar_date(2026, Month::January, 13).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn occurrences_between_leap_day() {
+        let bd = Birthday::new(Month::February, 29);
+        let from = Date::from_calendar_date(2096, Month::January, 1).unwrap();
+        let to = Date::from_calendar_date(2104, Month::December, 31).unwrap();
+
+        let years: Vec<i32> = bd
+            .occurrences_between(&from, &to)
+            .iter()
+            .map(|date| date.year())
+            .collect();
+
+        assert_eq!(years, vec![2096, 2104]);
+    }
+
+    #[test]
+    fn repetition() {
+        let date = |year, month, day| Date::from_calendar_date(year, month, day).unwrap();
+
+        assert_eq!(
+            Birthday::new(Month::March, 8)
+                .repetition(&date(2024, Month::March, 8))
+                .unwrap(),
+            (1, None)
+        );
+        assert_eq!(
+            Birthday::new(Month::February, 29)
+                .repetition(&date(2024, Month::February, 29))
+                .unwrap(),
+            (4, Some(date(2096, Month::February, 29)))
+        );
+    }
+
     #[test]
     fn from_date() {
         let date = Date::from_calendar_date(2024, Month::January, 13).unwrap();
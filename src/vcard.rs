@@ -0,0 +1,188 @@
+//! Tools for making vCard contacts.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+use crate::Character;
+
+/// The category every exported contact is tagged with.
+pub const VCARD_CATEGORY: &str = "Waifu Calendar";
+
+/// Placeholder year for birthdays without one, understood by Apple and Android clients.
+const OMITTED_YEAR: u16 = 1604;
+
+/// A version of the vCard format.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum VCardVersion {
+    /// vCard 3.0 ([RFC 2426](https://www.rfc-editor.org/rfc/rfc2426)).
+    V3,
+    /// vCard 4.0 ([RFC 6350](https://www.rfc-editor.org/rfc/rfc6350)).
+    #[default]
+    V4,
+}
+
+impl FromStr for VCardVersion {
+    type Err = anyhow::Error;
+
+    /// Parses `3.0` or `4.0`, with or without the `.0`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "3" | "3.0" => Ok(VCardVersion::V3),
+            "4" | "4.0" => Ok(VCardVersion::V4),
+            _ => bail!("Unknown vCard version {:?}", s),
+        }
+    }
+}
+
+/// Convert characters into vCard contacts with birthdays.
+pub trait BirthdayVCard {
+    /// Returns a vCard file with one contact per character.
+    fn to_vcf(&self, version: VCardVersion) -> String;
+}
+
+impl BirthdayVCard for Vec<Character> {
+    fn to_vcf(&self, version: VCardVersion) -> String {
+        self.iter()
+            .map(|character| character_to_vcard(character, version))
+            .collect()
+    }
+}
+
+fn character_to_vcard(character: &Character, version: VCardVersion) -> String {
+    let name = escape_text(character.name());
+    let birthday = character.birthday();
+
+    let mut lines = vec!["BEGIN:VCARD".to_string()];
+
+    match version {
+        VCardVersion::V3 => {
+            lines.push("VERSION:3.0".to_string());
+            lines.push(format!("FN:{}", name));
+            lines.push(format!("N:;{};;;", name));
+            lines.push(format!(
+                "BDAY;X-APPLE-OMIT-YEAR={0}:{0}-{1:02}-{2:02}",
+                OMITTED_YEAR,
+                birthday.month() as u8,
+                birthday.day()
+            ));
+        }
+        VCardVersion::V4 => {
+            lines.push("VERSION:4.0".to_string());
+            lines.push("KIND:individual".to_string());
+            lines.push(format!("FN:{}", name));
+            lines.push(format!(
+                "BDAY:--{:02}{:02}",
+                birthday.month() as u8,
+                birthday.day()
+            ));
+        }
+    }
+
+    if !character.url().is_empty() {
+        lines.push(format!("URL:{}", character.url()));
+        lines.push(format!("UID:{}", character.url()));
+    }
+
+    if let Some(image) = character.image() {
+        match version {
+            VCardVersion::V3 => lines.push(format!("PHOTO;VALUE=uri:{}", image)),
+            VCardVersion::V4 => lines.push(format!("PHOTO:{}", image)),
+        }
+    }
+
+    lines.push(format!("CATEGORIES:{}", escape_text(VCARD_CATEGORY)));
+    lines.push("END:VCARD".to_string());
+
+    let mut vcard = String::new();
+    for line in lines {
+        write_folded_line(&mut vcard, &line);
+    }
+
+    vcard
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+}
+
+/// Write a content line, folding it so no line is longer than 75 octets.
+fn write_folded_line(out: &mut String, line: &str) {
+    let mut length = 0;
+
+    for ch in line.chars() {
+        if length + ch.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            length = 1;
+        }
+
+        out.push(ch);
+        length += ch.len_utf8();
+    }
+
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use super::{BirthdayVCard, VCardVersion};
+    use crate::{Birthday, Character};
+
+    fn characters() -> Vec<Character> {
+        vec![Character::new(
+            "Frieren",
+            "https://anilist.co/character/176754",
+            Birthday::new(Month::March, 8),
+        )
+        .with_image("https://s4.anilist.co/character/frieren.png")]
+    }
+
+    #[test]
+    fn to_vcf_v4() {
+        assert_eq!(
+            characters().to_vcf(VCardVersion::V4),
+            "BEGIN:VCARD\r\n\
+            VERSION:4.0\r\n\
+            KIND:individual\r\n\
+            FN:Frieren\r\n\
+            BDAY:--0308\r\n\
+            URL:https://anilist.co/character/176754\r\n\
+            UID:https://anilist.co/character/176754\r\n\
+            PHOTO:https://s4.anilist.co/character/frieren.png\r\n\
+            CATEGORIES:Waifu Calendar\r\n\
+            END:VCARD\r\n"
+        );
+    }
+
+    #[test]
+    fn to_vcf_v3() {
+        let vcf = characters().to_vcf(VCardVersion::V3);
+
+        assert!(vcf.contains("VERSION:3.0\r\n"));
+        assert!(vcf.contains("N:;Frieren;;;\r\n"));
+        assert!(vcf.contains("BDAY;X-APPLE-OMIT-YEAR=1604:1604-03-08\r\n"));
+        assert!(vcf.contains("PHOTO;VALUE=uri:https://s4.anilist.co/character/frieren.png\r\n"));
+    }
+
+    #[test]
+    fn to_vcf_escapes_and_folds() {
+        let characters = vec![Character::new(
+            "Stark, the Warrior Who Is Very Brave Despite Being Afraid Most of the Time",
+            "",
+            Birthday::new(Month::February, 29),
+        )];
+
+        let vcf = characters.to_vcf(VCardVersion::V4);
+
+        assert!(vcf.lines().all(|line| line.len() <= 75));
+        assert!(vcf.replace("\r\n ", "").contains(
+            "FN:Stark\\, the Warrior Who Is Very Brave Despite Being Afraid Most of the Time\r\n"
+        ));
+        assert!(!vcf.contains("URL:"));
+    }
+}